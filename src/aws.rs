@@ -1,39 +1,30 @@
 use anyhow::{Context, Result};
 use std::collections::HashSet;
 use std::fs;
-use std::process::Command;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::config::ConnectionInfo;
+use crate::error::coded;
+use crate::redact::Secret;
+use crate::runner::CommandRunner;
+use crate::token_cache;
+
+#[cfg(feature = "native-iam")]
+mod native;
 
 pub fn list_aws_profiles() -> Vec<String> {
     let mut profiles = HashSet::new();
 
-    // Parse ~/.aws/credentials for [profile] sections
     if let Some(home) = dirs::home_dir() {
-        let credentials_path = home.join(".aws").join("credentials");
-        if let Ok(content) = fs::read_to_string(&credentials_path) {
-            for line in content.lines() {
-                let line = line.trim();
-                if line.starts_with('[') && line.ends_with(']') {
-                    let profile_name = &line[1..line.len() - 1];
-                    profiles.insert(profile_name.to_string());
-                }
-            }
+        // Parse ~/.aws/credentials for [profile] sections
+        if let Some(content) = read_text_lossy(&home.join(".aws").join("credentials")) {
+            profiles.extend(parse_credentials_profiles(&content));
         }
 
         // Parse ~/.aws/config for [profile X] sections
-        let config_path = home.join(".aws").join("config");
-        if let Ok(content) = fs::read_to_string(&config_path) {
-            for line in content.lines() {
-                let line = line.trim();
-                if line.starts_with('[') && line.ends_with(']') {
-                    let section = &line[1..line.len() - 1];
-                    if section.starts_with("profile ") {
-                        let profile_name = section.strip_prefix("profile ").unwrap();
-                        profiles.insert(profile_name.to_string());
-                    } else if section == "default" {
-                        profiles.insert("default".to_string());
-                    }
-                }
-            }
+        if let Some(content) = read_text_lossy(&home.join(".aws").join("config")) {
+            profiles.extend(parse_config_profiles(&content).into_iter().map(|p| p.name));
         }
     }
 
@@ -49,11 +40,224 @@ pub fn list_aws_profiles() -> Vec<String> {
     profiles
 }
 
+const UTF8_BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+
+/// Read a file as text even if it isn't clean UTF-8 (invalid bytes are
+/// replaced) and strip a leading UTF-8 BOM, as written by some Windows editors.
+fn read_text_lossy(path: &Path) -> Option<String> {
+    let bytes = fs::read(path).ok()?;
+    let bytes = bytes.strip_prefix(UTF8_BOM).unwrap_or(&bytes);
+    Some(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// A profile declared in `~/.aws/config`, along with the `sso-session`
+/// section it authenticates through (if any).
+#[derive(Debug, PartialEq)]
+pub struct ConfigProfile {
+    pub name: String,
+    pub sso_session: Option<String>,
+}
+
+/// Trimmed lines of an INI-style file, tolerating CRLF line endings.
+fn ini_lines(content: &str) -> impl Iterator<Item = &str> {
+    content
+        .split('\n')
+        .map(|line| line.trim_end_matches('\r').trim())
+}
+
+fn section_header(line: &str) -> Option<&str> {
+    line.strip_prefix('[')?.strip_suffix(']').map(str::trim)
+}
+
+fn parse_credentials_profiles(content: &str) -> Vec<String> {
+    ini_lines(content)
+        .filter_map(section_header)
+        .map(str::to_string)
+        .collect()
+}
+
+/// Parse the profiles in an AWS config file. `[sso-session X]`, `[services X]`
+/// and other non-profile sections are skipped.
+fn parse_config_profiles(content: &str) -> Vec<ConfigProfile> {
+    let mut profiles: Vec<ConfigProfile> = Vec::new();
+    let mut in_profile = false;
+
+    for line in ini_lines(content) {
+        if let Some(section) = section_header(line) {
+            let name = if let Some(profile_name) = section.strip_prefix("profile ") {
+                Some(profile_name.trim())
+            } else if section == "default" {
+                Some("default")
+            } else {
+                None
+            };
+
+            in_profile = name.is_some();
+            if let Some(name) = name {
+                profiles.push(ConfigProfile {
+                    name: name.to_string(),
+                    sso_session: None,
+                });
+            }
+        } else if in_profile
+            && let Some((key, value)) = line.split_once('=')
+            && key.trim() == "sso_session"
+            && let Some(profile) = profiles.last_mut()
+        {
+            profile.sso_session = Some(value.trim().to_string());
+        }
+    }
+
+    profiles
+}
+
+/// Look up the `sso-session` a profile authenticates through, so SSO login
+/// can refresh the shared session rather than a single profile.
+pub fn profile_sso_session(profile: Option<&str>) -> Option<String> {
+    let profile = profile.unwrap_or("default");
+    let content = read_text_lossy(&dirs::home_dir()?.join(".aws").join("config"))?;
+    parse_config_profiles(&content)
+        .into_iter()
+        .find(|p| p.name == profile)
+        .and_then(|p| p.sso_session)
+}
+
+/// Generate an RDS IAM auth token. With the `native-iam` feature (on by
+/// default) the token is signed in-process, falling back to the AWS CLI if
+/// that fails. `region` overrides the profile's, which has to match the
+/// instance's for the token to be accepted. A token signed for the same
+/// endpoint, role, profile and region in the last 14 minutes is reused.
 pub fn generate_iam_token(
+    runner: &dyn CommandRunner,
+    host: &str,
+    port: u16,
+    username: &str,
+    profile: Option<&str>,
+    region: Option<&str>,
+) -> Result<Secret> {
+    let key = token_cache::key(host, port, username, profile, region);
+    if let Some(token) = token_cache::get(&key) {
+        return Ok(token);
+    }
+    let token = sign_iam_token(runner, host, port, username, profile, region)?;
+    token_cache::put(&key, &token);
+    Ok(Secret::new(token))
+}
+
+fn sign_iam_token(
+    runner: &dyn CommandRunner,
+    host: &str,
+    port: u16,
+    username: &str,
+    profile: Option<&str>,
+    region: Option<&str>,
+) -> Result<String> {
+    #[cfg(feature = "native-iam")]
+    match native::generate_iam_token(host, port, username, profile, region) {
+        Ok(token) => return Ok(token),
+        Err(e) => eprintln!(
+            "Native IAM token generation failed ({:#}); falling back to the AWS CLI",
+            e
+        ),
+    }
+
+    generate_iam_token_cli(runner, host, port, username, profile, region)
+}
+
+/// The region to sign `info`'s IAM tokens for: its `region`, or the one in
+/// its RDS endpoint. `None` leaves it to the AWS profile.
+pub fn iam_region(info: &ConnectionInfo) -> Option<String> {
+    info.region.clone().or_else(|| rds_region(&info.host))
+}
+
+/// Why a profile in a fallback chain couldn't produce a token.
+#[derive(Debug, PartialEq)]
+enum ProfileFailure {
+    /// The profile's SSO session has expired; it was skipped
+    NeedsSsoLogin,
+    Failed(String),
+}
+
+/// Generate a token for an IAM connection. An explicit `profile` always
+/// wins; otherwise the connection's `profiles` are tried in order, and with
+/// neither the default credential chain is used. Returns the token and the
+/// profile that produced it, when one was chosen from the connection.
+pub fn iam_token_for(
+    runner: &dyn CommandRunner,
+    info: &ConnectionInfo,
+    profile: Option<&str>,
+    allow_sso_login: bool,
+) -> Result<(Secret, Option<String>)> {
+    let region = iam_region(info);
+    if profile.is_some() || info.profiles.is_empty() {
+        let token = generate_iam_token(runner, &info.host, info.port, &info.username, profile, region.as_deref())?;
+        return Ok((token, None));
+    }
+
+    let (token, used) = first_working_profile(
+        &info.profiles,
+        allow_sso_login,
+        |profile| generate_iam_token(runner, &info.host, info.port, &info.username, Some(profile), region.as_deref()),
+        |profile| sso_login(runner, Some(profile)),
+    )?;
+    Ok((token, Some(used)))
+}
+
+/// Try each profile until one yields a token. Profiles that need an SSO
+/// login are skipped, or logged in and retried once if `allow_sso_login`.
+fn first_working_profile<T>(
+    profiles: &[String],
+    allow_sso_login: bool,
+    mut generate: impl FnMut(&str) -> Result<T>,
+    mut login: impl FnMut(&str) -> Result<()>,
+) -> Result<(T, String)> {
+    let mut failures = Vec::new();
+
+    for profile in profiles {
+        let failure = match generate(profile) {
+            Ok(token) => return Ok((token, profile.clone())),
+            Err(e) if needs_sso_login(&e.to_string()) => {
+                if allow_sso_login {
+                    match login(profile).and_then(|()| generate(profile)) {
+                        Ok(token) => return Ok((token, profile.clone())),
+                        Err(e) => ProfileFailure::Failed(format!("{:#}", e)),
+                    }
+                } else {
+                    ProfileFailure::NeedsSsoLogin
+                }
+            }
+            Err(e) => ProfileFailure::Failed(format!("{:#}", e)),
+        };
+        failures.push((profile, failure));
+    }
+
+    let mut message = String::from("No AWS profile could generate an IAM token:");
+    for (profile, failure) in &failures {
+        match failure {
+            ProfileFailure::NeedsSsoLogin => {
+                message.push_str(&format!("\n  {}: SSO session expired (skipped)", profile))
+            }
+            ProfileFailure::Failed(reason) => {
+                message.push_str(&format!("\n  {}: {}", profile, reason.trim()))
+            }
+        }
+    }
+    if failures
+        .iter()
+        .any(|(_, failure)| *failure == ProfileFailure::NeedsSsoLogin)
+    {
+        message.push_str("\nPass --sso-login to log in to skipped profiles and retry them.");
+    }
+    Err(coded("aws_cli_failed", message))
+}
+
+fn generate_iam_token_cli(
+    runner: &dyn CommandRunner,
     host: &str,
     port: u16,
     username: &str,
     profile: Option<&str>,
+    region: Option<&str>,
 ) -> Result<String> {
     let mut cmd = Command::new("aws");
     cmd.args([
@@ -70,14 +274,20 @@ pub fn generate_iam_token(
     if let Some(profile_name) = profile {
         cmd.args(["--profile", profile_name]);
     }
+    if let Some(region) = region {
+        cmd.args(["--region", region]);
+    }
 
-    let output = cmd
-        .output()
+    let output = runner
+        .output(&mut cmd)
         .context("Failed to execute AWS CLI command. Make sure AWS CLI is installed and configured.")?;
 
     if !output.status.success() {
         let error_msg = String::from_utf8_lossy(&output.stderr);
-        anyhow::bail!("AWS CLI command failed: {}", error_msg);
+        return Err(coded(
+            "aws_cli_failed",
+            format!("AWS CLI command failed: {}", error_msg),
+        ));
     }
 
     let iam_token = String::from_utf8(output.stdout)
@@ -93,12 +303,12 @@ pub fn generate_iam_token(
 }
 
 #[allow(dead_code)]
-pub fn verify_aws_profile(profile: &str) -> Result<()> {
+pub fn verify_aws_profile(runner: &dyn CommandRunner, profile: &str) -> Result<()> {
     let mut cmd = Command::new("aws");
     cmd.args(["sts", "get-caller-identity", "--profile", profile]);
 
-    let output = cmd
-        .output()
+    let output = runner
+        .output(&mut cmd)
         .context("Failed to execute AWS CLI command")?;
 
     if !output.status.success() {
@@ -109,6 +319,32 @@ pub fn verify_aws_profile(profile: &str) -> Result<()> {
     Ok(())
 }
 
+pub fn sso_login(runner: &dyn CommandRunner, profile: Option<&str>) -> Result<()> {
+    let mut cmd = Command::new("aws");
+    cmd.arg("sso").arg("login");
+
+    // Log in to the shared sso-session when the profile uses one, so every
+    // profile behind that session is refreshed at once
+    if let Some(session) = profile_sso_session(profile) {
+        cmd.args(["--sso-session", &session]);
+    } else if let Some(profile_name) = profile {
+        cmd.args(["--profile", profile_name]);
+    }
+
+    cmd.stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit());
+
+    let status = runner.status(&mut cmd).context(
+        "Failed to execute AWS SSO login. Make sure AWS CLI v2 is installed.",
+    )?;
+
+    if !status.success() {
+        anyhow::bail!("AWS SSO login exited with error code: {:?}", status.code);
+    }
+    Ok(())
+}
+
 pub fn needs_sso_login(error_msg: &str) -> bool {
     let error_lower = error_msg.to_lowercase();
     error_lower.contains("sso")
@@ -117,3 +353,303 @@ pub fn needs_sso_login(error_msg: &str) -> bool {
         || error_lower.contains("the sso session")
         || error_lower.contains("error loading sso")
 }
+
+/// An RDS endpoint, `<id>.<hash>.<region>.rds.amazonaws.com` (or
+/// `.rds.amazonaws.com.cn` in China), lowercased.
+struct RdsEndpoint {
+    id: String,
+    hash: String,
+    region: String,
+    console_domain: &'static str,
+}
+
+fn parse_rds_endpoint(host: &str) -> Option<RdsEndpoint> {
+    let host = host.to_lowercase();
+    let (prefix, console_domain) = if let Some(prefix) = host.strip_suffix(".rds.amazonaws.com") {
+        (prefix, "console.aws.amazon.com")
+    } else if let Some(prefix) = host.strip_suffix(".rds.amazonaws.com.cn") {
+        (prefix, "console.amazonaws.cn")
+    } else {
+        return None;
+    };
+
+    let parts: Vec<&str> = prefix.split('.').collect();
+    let [id, hash, region] = parts[..] else {
+        return None;
+    };
+    Some(RdsEndpoint {
+        id: id.to_string(),
+        hash: hash.to_string(),
+        region: region.to_string(),
+        console_domain,
+    })
+}
+
+/// The AWS region of an RDS endpoint, or `None` if `host` isn't one.
+pub fn rds_region(host: &str) -> Option<String> {
+    parse_rds_endpoint(host).map(|endpoint| endpoint.region)
+}
+
+/// AWS console page for the RDS instance, cluster or proxy behind an RDS
+/// endpoint, or `None` if `host` isn't one.
+///
+/// The endpoint's hash is prefixed with `cluster-`, `cluster-ro-` or
+/// `proxy-` for those endpoint types. Custom cluster endpoints are named
+/// independently of their cluster, so they (and anything unrecognised) get
+/// the region's database list instead.
+pub fn rds_console_url(host: &str) -> Option<String> {
+    let RdsEndpoint {
+        id,
+        hash,
+        region,
+        console_domain,
+    } = parse_rds_endpoint(host)?;
+
+    let fragment = if hash.starts_with("cluster-custom-") {
+        "databases:".to_string()
+    } else if hash.starts_with("cluster-") {
+        format!("database:id={};is-cluster=true", id)
+    } else if hash.starts_with("proxy-") {
+        format!("proxy:id={}", id)
+    } else {
+        format!("database:id={};is-cluster=false", id)
+    };
+
+    Some(format!(
+        "https://{region}.{console_domain}/rds/home?region={region}#{fragment}"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::mock::MockRunner;
+
+    #[test]
+    fn console_url_from_rds_endpoints() {
+        assert_eq!(
+            rds_console_url("orders.abc123.us-east-1.rds.amazonaws.com").unwrap(),
+            "https://us-east-1.console.aws.amazon.com/rds/home?region=us-east-1#database:id=orders;is-cluster=false"
+        );
+        assert_eq!(
+            rds_console_url("shop.cluster-ro-abc123.eu-west-1.rds.amazonaws.com").unwrap(),
+            "https://eu-west-1.console.aws.amazon.com/rds/home?region=eu-west-1#database:id=shop;is-cluster=true"
+        );
+        assert!(rds_console_url("pool.proxy-abc123.us-west-2.rds.amazonaws.com")
+            .unwrap()
+            .ends_with("#proxy:id=pool"));
+        assert!(rds_console_url("reports.cluster-custom-abc123.us-east-1.rds.amazonaws.com")
+            .unwrap()
+            .ends_with("#databases:"));
+        assert!(rds_console_url("db.abc123.cn-north-1.rds.amazonaws.com.cn")
+            .unwrap()
+            .starts_with("https://cn-north-1.console.amazonaws.cn/rds/home?region=cn-north-1#"));
+
+        assert_eq!(rds_console_url("db.example.com"), None);
+        assert_eq!(rds_console_url("abc123.us-east-1.rds.amazonaws.com"), None);
+    }
+
+    #[test]
+    fn iam_region_defaults_to_the_endpoints() {
+        let mut info = ConnectionInfo {
+            host: "orders.abc123.eu-west-1.rds.amazonaws.com".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(iam_region(&info).as_deref(), Some("eu-west-1"));
+        info.region = Some("us-east-1".to_string());
+        assert_eq!(iam_region(&info).as_deref(), Some("us-east-1"));
+        info.region = None;
+        info.host = "db.internal".to_string();
+        assert_eq!(iam_region(&info), None);
+    }
+
+    #[test]
+    fn iam_token_passes_endpoint_and_profile_to_aws_cli() {
+        let runner = MockRunner::succeeding("token-value\n");
+        let token = generate_iam_token_cli(
+            &runner,
+            "db.abc.us-east-1.rds.amazonaws.com",
+            5432,
+            "app",
+            Some("prod"),
+            Some("us-east-1"),
+        )
+        .unwrap();
+
+        assert_eq!(token, "token-value");
+        let call = runner.last_call();
+        assert_eq!(call.program, "aws");
+        assert_eq!(
+            call.args,
+            vec![
+                "rds",
+                "generate-db-auth-token",
+                "--hostname",
+                "db.abc.us-east-1.rds.amazonaws.com",
+                "--port",
+                "5432",
+                "--username",
+                "app",
+                "--profile",
+                "prod",
+                "--region",
+                "us-east-1",
+            ]
+        );
+    }
+
+    #[test]
+    fn iam_token_surfaces_cli_errors() {
+        let runner = MockRunner::failing(255, "Error loading SSO Token");
+        let err = generate_iam_token_cli(&runner, "host", 5432, "app", None, None).unwrap_err();
+        assert!(needs_sso_login(&err.to_string()));
+    }
+
+    #[test]
+    fn iam_token_rejects_empty_output() {
+        let runner = MockRunner::succeeding("  \n");
+        assert!(generate_iam_token_cli(&runner, "host", 5432, "app", None, None).is_err());
+    }
+
+    #[test]
+    fn profile_chain_skips_expired_sso_and_reports_the_one_used() {
+        let profiles = vec!["primary".to_string(), "backup".to_string()];
+        let mut tried = Vec::new();
+        let (token, used) = first_working_profile(
+            &profiles,
+            false,
+            |profile| {
+                tried.push(profile.to_string());
+                match profile {
+                    "primary" => anyhow::bail!("Error loading SSO Token: Token has expired"),
+                    _ => Ok("backup-token".to_string()),
+                }
+            },
+            |_| panic!("should not log in without --sso-login"),
+        )
+        .unwrap();
+
+        assert_eq!(token, "backup-token");
+        assert_eq!(used, "backup");
+        assert_eq!(tried, vec!["primary", "backup"]);
+    }
+
+    #[test]
+    fn profile_chain_logs_in_when_allowed() {
+        let profiles = vec!["primary".to_string()];
+        let logged_in = std::cell::Cell::new(false);
+        let (_, used) = first_working_profile(
+            &profiles,
+            true,
+            |_| {
+                if logged_in.get() {
+                    Ok("token".to_string())
+                } else {
+                    anyhow::bail!("The SSO session associated with this profile has expired")
+                }
+            },
+            |_| {
+                logged_in.set(true);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(used, "primary");
+    }
+
+    #[test]
+    fn profile_chain_lists_every_failure() {
+        let profiles = vec!["sso".to_string(), "keys".to_string()];
+        let err = first_working_profile::<Secret>(
+            &profiles,
+            false,
+            |profile| match profile {
+                "sso" => anyhow::bail!("Error loading SSO Token"),
+                _ => anyhow::bail!("InvalidClientTokenId"),
+            },
+            |_| Ok(()),
+        )
+        .unwrap_err();
+
+        let message = err.to_string();
+        assert!(message.contains("sso: SSO session expired (skipped)"));
+        assert!(message.contains("keys: InvalidClientTokenId"));
+        assert!(message.contains("--sso-login"));
+    }
+
+    #[test]
+    fn verify_profile_calls_sts() {
+        let runner = MockRunner::succeeding("{}");
+        verify_aws_profile(&runner, "dev").unwrap();
+        assert_eq!(
+            runner.last_call().args,
+            vec!["sts", "get-caller-identity", "--profile", "dev"]
+        );
+    }
+
+    #[test]
+    fn parses_bom_prefixed_crlf_config() {
+        let path = std::env::temp_dir().join(format!("pg-vault-aws-config-{}", std::process::id()));
+        let mut bytes = UTF8_BOM.to_vec();
+        bytes.extend_from_slice(b"[default]\r\nregion = us-east-1\r\n\r\n[profile staging]\r\n");
+        // A stray Latin-1 byte in a comment must not hide the profiles
+        bytes.extend_from_slice(b"# caf\xe9\r\n[profile prod]\r\nregion = eu-west-1\r\n");
+        fs::write(&path, bytes).unwrap();
+
+        let content = read_text_lossy(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let names: Vec<String> = parse_config_profiles(&content)
+            .into_iter()
+            .map(|p| p.name)
+            .collect();
+        assert_eq!(names, vec!["default", "staging", "prod"]);
+    }
+
+    #[test]
+    fn skips_sso_session_sections_and_links_profiles() {
+        let content = "\
+[default]
+region = us-east-1
+
+[sso-session corp]
+sso_start_url = https://corp.awsapps.com/start
+sso_region = us-east-1
+
+[profile dev]
+sso_session = corp
+sso_account_id = 111111111111
+sso_role_name = Developer
+
+[services local-endpoints]
+rds =
+  endpoint_url = http://localhost:4566
+
+[profile legacy]
+aws_access_key_id = x
+";
+        assert_eq!(
+            parse_config_profiles(content),
+            vec![
+                ConfigProfile {
+                    name: "default".to_string(),
+                    sso_session: None,
+                },
+                ConfigProfile {
+                    name: "dev".to_string(),
+                    sso_session: Some("corp".to_string()),
+                },
+                ConfigProfile {
+                    name: "legacy".to_string(),
+                    sso_session: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_credentials_sections() {
+        let content = "[default]\naws_access_key_id = x\n\n[ci]\naws_access_key_id = y\n";
+        assert_eq!(parse_credentials_profiles(content), vec!["default", "ci"]);
+    }
+}
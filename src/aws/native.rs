@@ -0,0 +1,86 @@
+//! In-process RDS IAM token generation using the AWS SDK credential chain
+//! and SigV4 presigning, avoiding the `aws` CLI subprocess.
+
+use anyhow::{Context, Result};
+use aws_config::BehaviorVersion;
+use aws_credential_types::provider::ProvideCredentials;
+use aws_sigv4::http_request::{
+    sign, SignableBody, SignableRequest, SignatureLocation, SigningSettings,
+};
+use aws_sigv4::sign::v4;
+use std::time::{Duration, SystemTime};
+
+/// RDS auth tokens are valid for 15 minutes.
+const TOKEN_LIFETIME: Duration = Duration::from_secs(15 * 60);
+
+pub fn generate_iam_token(
+    host: &str,
+    port: u16,
+    username: &str,
+    profile: Option<&str>,
+    region: Option<&str>,
+) -> Result<String> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .context("Failed to start async runtime for AWS SDK")?;
+
+    let mut loader = aws_config::defaults(BehaviorVersion::latest());
+    if let Some(profile) = profile {
+        loader = loader.profile_name(profile);
+    }
+    if let Some(region) = region {
+        loader = loader.region(aws_config::Region::new(region.to_string()));
+    }
+    let config = runtime.block_on(loader.load());
+
+    let region = config
+        .region()
+        .context("No AWS region configured for this profile")?
+        .to_string();
+    let credentials = runtime
+        .block_on(
+            config
+                .credentials_provider()
+                .context("No AWS credentials provider configured")?
+                .provide_credentials(),
+        )
+        .context("Failed to load AWS credentials")?;
+
+    let identity = credentials.into();
+    let mut settings = SigningSettings::default();
+    settings.expires_in = Some(TOKEN_LIFETIME);
+    settings.signature_location = SignatureLocation::QueryParams;
+
+    let params = v4::SigningParams::builder()
+        .identity(&identity)
+        .region(&region)
+        .name("rds-db")
+        .time(SystemTime::now())
+        .settings(settings)
+        .build()
+        .context("Failed to build SigV4 signing parameters")?
+        .into();
+
+    let url = format!(
+        "https://{}:{}/?Action=connect&DBUser={}",
+        host,
+        port,
+        urlencoding::encode(username)
+    );
+    let signable = SignableRequest::new("GET", &url, std::iter::empty(), SignableBody::Bytes(&[]))
+        .context("Failed to build signable request")?;
+    let (instructions, _signature) = sign(signable, &params)
+        .context("Failed to sign RDS connect request")?
+        .into_parts();
+
+    let mut request = http::Request::builder()
+        .uri(&url)
+        .body(())
+        .context("Failed to build RDS connect request")?;
+    instructions.apply_to_request_http1x(&mut request);
+
+    // The token is the presigned URL without its scheme
+    let uri = request.uri().to_string();
+    Ok(uri.strip_prefix("https://").unwrap_or(&uri).to_string())
+}
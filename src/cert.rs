@@ -0,0 +1,186 @@
+use anyhow::{Context, Result};
+use std::fs;
+use x509_parser::pem::Pem;
+
+use crate::config::{load_settings, unix_now, ConnectionInfo};
+use crate::psql::effective_sslmode;
+
+/// How many days ahead to warn about expiring certificates, unless the
+/// `cert_expiry_warning_days` setting says otherwise.
+pub const DEFAULT_WARNING_DAYS: u32 = 30;
+
+const DAY: i64 = 24 * 60 * 60;
+
+/// Warn before connecting to `name` if its root or client certificate has
+/// expired or will within the warning window. Only a warning: libpq has the
+/// final say, and a certificate that can't be read is reported and skipped.
+pub fn warn_if_expiring(name: &str, info: &ConnectionInfo) {
+    let files = cert_files(info);
+    if files.is_empty() {
+        return;
+    }
+
+    let days = match load_settings() {
+        Ok(settings) => settings.cert_expiry_warning_days.unwrap_or(DEFAULT_WARNING_DAYS),
+        Err(e) => {
+            eprintln!("Warning: {:#}", e);
+            DEFAULT_WARNING_DAYS
+        }
+    };
+    let now = unix_now() as i64;
+    for (field, path) in files {
+        match fs::read(path)
+            .with_context(|| format!("Could not read {}", path))
+            .and_then(|pem| earliest_expiry(&pem))
+        {
+            Ok(not_after) => {
+                if let Some(warning) = expiry_warning(name, field, path, not_after, now, days) {
+                    eprintln!("{}", warning);
+                }
+            }
+            Err(e) => eprintln!("Warning: could not check when the {} for '{}' expires: {:#}", field, name, e),
+        }
+    }
+}
+
+/// The certificate files libpq will use for `info`. The root certificate
+/// only matters when the server's certificate is being verified.
+fn cert_files(info: &ConnectionInfo) -> Vec<(&'static str, &str)> {
+    let mut files = Vec::new();
+    if matches!(effective_sslmode(info).as_str(), "verify-ca" | "verify-full")
+        && let Some(root) = &info.sslrootcert
+    {
+        files.push(("sslrootcert", root.as_str()));
+    }
+    if let Some(cert) = &info.sslcert {
+        files.push(("sslcert", cert.as_str()));
+    }
+    files
+}
+
+/// When the first certificate in a PEM file expires, as seconds since the
+/// Unix epoch. A root file can hold a bundle, and is only as good as the
+/// certificate in it that expires first.
+fn earliest_expiry(pem: &[u8]) -> Result<i64> {
+    let mut earliest: Option<i64> = None;
+    for block in Pem::iter_from_buffer(pem) {
+        let block = block.context("Not a valid PEM file")?;
+        if block.label != "CERTIFICATE" {
+            continue;
+        }
+        let cert = block
+            .parse_x509()
+            .map_err(|e| anyhow::anyhow!("Could not parse a certificate: {}", e))?;
+        let not_after = cert.validity().not_after.timestamp();
+        earliest = Some(earliest.map_or(not_after, |earliest| earliest.min(not_after)));
+    }
+    earliest.context("No certificates found")
+}
+
+fn expiry_warning(name: &str, field: &str, path: &str, not_after: i64, now: i64, days: u32) -> Option<String> {
+    let remaining = not_after - now;
+    if remaining <= 0 {
+        Some(format!(
+            "Warning: the {} for '{}' ({}) expired {} ago; connections using it will fail.",
+            field,
+            name,
+            path,
+            format_days(-remaining)
+        ))
+    } else if remaining < i64::from(days) * DAY {
+        Some(format!(
+            "Warning: the {} for '{}' ({}) expires in {}; renew it before connections start failing.",
+            field,
+            name,
+            path,
+            format_days(remaining)
+        ))
+    } else {
+        None
+    }
+}
+
+fn format_days(seconds: i64) -> String {
+    match seconds / DAY {
+        0 => "less than a day".to_string(),
+        1 => "1 day".to_string(),
+        days => format!("{} days", days),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Self-signed, expiring 2027-01-01T00:00:00Z.
+    const CERT_2027: &str = "-----BEGIN CERTIFICATE-----
+MIIBhTCCASugAwIBAgIUFz7271ILLy4qKcScyBpo6sNq+K8wCgYIKoZIzj0EAwIw
+GDEWMBQGA1UEAwwNcGctdmF1bHQgdGVzdDAeFw0yNTAxMDEwMDAwMDBaFw0yNzAx
+MDEwMDAwMDBaMBgxFjAUBgNVBAMMDXBnLXZhdWx0IHRlc3QwWTATBgcqhkjOPQIB
+BggqhkjOPQMBBwNCAATF9KPO5YtN4p4YiMD1O2ENQAwsFSbU6Fb1jqDj+orA0MU4
+TQsrEKLUqwKyla/k4g7XHpdCCBcgT00hTHcuWRRpo1MwUTAdBgNVHQ4EFgQUl3nt
+8p6iXLPiXh5PeQJYwFnG988wHwYDVR0jBBgwFoAUl3nt8p6iXLPiXh5PeQJYwFnG
+988wDwYDVR0TAQH/BAUwAwEB/zAKBggqhkjOPQQDAgNIADBFAiAxT/o0VZRPkzGH
+K0+fT90Bxn4GGu0jWHFDTz+LyrwFqwIhAOLdkmWtvG3VMQJz2qtnmsPmQhRm76qt
+Dw7a76d4cQuL
+-----END CERTIFICATE-----
+";
+
+    /// Self-signed, expiring 2026-06-01T00:00:00Z.
+    const CERT_2026: &str = "-----BEGIN CERTIFICATE-----
+MIIBiTCCAS+gAwIBAgIULkdPYzigAS859j+NnqABGYWMkoAwCgYIKoZIzj0EAwIw
+GjEYMBYGA1UEAwwPcGctdmF1bHQgb2xkIENBMB4XDTI1MDEwMTAwMDAwMFoXDTI2
+MDYwMTAwMDAwMFowGjEYMBYGA1UEAwwPcGctdmF1bHQgb2xkIENBMFkwEwYHKoZI
+zj0CAQYIKoZIzj0DAQcDQgAEIsNOGAUEfdTOKB5nqkBy/piwJWxA9MRDsqr+hSHV
+ojRjn3az9S5mevD3TH3MO9DD1r3EwBG83m29/d2vq6dCAaNTMFEwHQYDVR0OBBYE
+FFjsizOMUTIBZI5sUKhQhr/VOWhZMB8GA1UdIwQYMBaAFFjsizOMUTIBZI5sUKhQ
+hr/VOWhZMA8GA1UdEwEB/wQFMAMBAf8wCgYIKoZIzj0EAwIDSAAwRQIhAPEetFEG
+rbMi1fpeX9x6pwwL3ALOH+PlwvBqNClxTYg6AiBDvUT7t99s3LRc7o5T0/cejxQv
+ZWrMIAAArV5WcboVnw==
+-----END CERTIFICATE-----
+";
+
+    const JAN_2027: i64 = 1_798_761_600;
+    const JUN_2026: i64 = 1_780_272_000;
+
+    #[test]
+    fn a_bundle_expires_with_its_first_certificate() {
+        assert_eq!(earliest_expiry(CERT_2027.as_bytes()).unwrap(), JAN_2027);
+        let bundle = format!("{}{}", CERT_2027, CERT_2026);
+        assert_eq!(earliest_expiry(bundle.as_bytes()).unwrap(), JUN_2026);
+        assert!(earliest_expiry(b"not a certificate").is_err());
+    }
+
+    #[test]
+    fn only_certificates_inside_the_window_are_reported() {
+        let warn = |now| expiry_warning("prod", "sslcert", "/certs/client.crt", JAN_2027, now, 30);
+
+        assert_eq!(warn(JAN_2027 - 31 * DAY), None);
+        assert_eq!(
+            warn(JAN_2027 - 12 * DAY).unwrap(),
+            "Warning: the sslcert for 'prod' (/certs/client.crt) expires in 12 days; renew it before connections start failing."
+        );
+        assert!(warn(JAN_2027 - 60).unwrap().contains("expires in less than a day"));
+        assert!(warn(JAN_2027 + DAY).unwrap().contains("expired 1 day ago"));
+    }
+
+    #[test]
+    fn root_certificate_is_only_checked_when_verifying() {
+        let info = ConnectionInfo {
+            sslrootcert: Some("/certs/root.crt".to_string()),
+            sslcert: Some("/certs/client.crt".to_string()),
+            sslmode: Some("require".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(cert_files(&info), [("sslcert", "/certs/client.crt")]);
+
+        let info = ConnectionInfo {
+            sslmode: Some("verify-full".to_string()),
+            ..info
+        };
+        assert_eq!(
+            cert_files(&info),
+            [("sslrootcert", "/certs/root.crt"), ("sslcert", "/certs/client.crt")]
+        );
+    }
+}
@@ -0,0 +1,65 @@
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+/// Clipboard tools to try in order, with the arguments that make them read
+/// the text from stdin.
+const TOOLS: &[(&str, &[&str])] = &[
+    ("pbcopy", &[]),
+    ("wl-copy", &[]),
+    ("xclip", &["-selection", "clipboard"]),
+    ("xsel", &["--clipboard", "--input"]),
+    ("clip.exe", &[]),
+];
+
+/// Put `text` on the clipboard and say how: with the first clipboard tool
+/// that works, or failing that an OSC 52 escape asking the terminal to do
+/// it (which also works over SSH).
+pub fn copy(text: &str) -> Result<&'static str> {
+    for (program, args) in TOOLS {
+        if pipe_to(program, args, text).is_ok() {
+            return Ok(program);
+        }
+    }
+
+    let mut stdout = io::stdout();
+    stdout
+        .write_all(osc52(text).as_bytes())
+        .and_then(|()| stdout.flush())
+        .context("Could not write to the terminal")?;
+    Ok("terminal")
+}
+
+fn pipe_to(program: &str, args: &[&str], text: &str) -> io::Result<()> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    // Dropped after writing, so the tool sees end of input
+    child.stdin.take().expect("stdin is piped").write_all(text.as_bytes())?;
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("{} exited with {}", program, status)));
+    }
+    Ok(())
+}
+
+/// The escape sequence that sets the system clipboard in terminals that
+/// support OSC 52.
+fn osc52(text: &str) -> String {
+    format!("\x1b]52;c;{}\x07", STANDARD.encode(text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn osc52_sends_the_text_base64_encoded() {
+        assert_eq!(osc52("pg-vault store app"), "\x1b]52;c;cGctdmF1bHQgc3RvcmUgYXBw\x07");
+    }
+}
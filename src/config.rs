@@ -1,42 +1,1532 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
-use std::path::PathBuf;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-#[derive(Serialize, Deserialize, Clone)]
+use crate::error::coded;
+use crate::redact::{redact, Secret};
+use crate::psql::{engine_profile, CHANNEL_BINDINGS, DRIVERS, ENGINES, SSLMODES};
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
 pub struct ConnectionInfo {
     pub host: String,
+    /// Read replica of `host`, used instead of it with `--replica`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replica_host: Option<String>,
     pub port: u16,
     pub database: String,
     pub username: String,
+    /// Database for `test` and other maintenance work (e.g. `postgres`),
+    /// when it isn't `database`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maintenance_database: Option<String>,
     #[serde(default)]
     pub iam_auth: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_version: Option<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bastion: Option<String>,
+    /// SSH host to tunnel through, as `[user@]host[:port]`, for a bastion
+    /// only this connection uses
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssh_jump: Option<String>,
+    /// GCP Cloud SQL instance (`project:region:instance`) reached through a
+    /// local `cloud-sql-proxy` instead of `host`/`port`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cloud_sql_instance: Option<String>,
+    /// Local port to forward through the bastion or `cloud-sql-proxy` on,
+    /// moving on to the next free one if it's taken (any free port if unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local_port: Option<u16>,
+    /// AWS profiles to try, in order, when generating IAM tokens
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub profiles: Vec<String>,
+    /// AWS region to sign IAM tokens for, when it isn't the profile's
+    /// (defaults to the region in an RDS endpoint)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    /// Launch psql with autocommit off and a warning prompt
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub protected: bool,
+    /// What must be typed before connecting to a protected connection
+    /// (defaults to the connection name)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confirm_phrase: Option<String>,
+    /// libpq `sslmode` (disable, allow, prefer, require, verify-ca, verify-full)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sslmode: Option<String>,
+    /// libpq `channel_binding` (disable, prefer, require) for SCRAM auth
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel_binding: Option<String>,
+    /// libpq `sslrootcert`: CA certificate file to verify the server with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sslrootcert: Option<String>,
+    /// libpq `sslcert`: client certificate file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sslcert: Option<String>,
+    /// libpq `sslkey`: private key for `sslcert`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sslkey: Option<String>,
+    /// libpq `connect_timeout`: seconds to wait for the server (0 waits forever)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_timeout: Option<u32>,
+    /// libpq `keepalives_idle`: seconds idle before TCP keepalive probes start,
+    /// so NAT gateways and firewalls don't drop a quiet session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keepalives_idle: Option<u32>,
+    /// libpq `keepalives_interval`: seconds between unanswered probes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keepalives_interval: Option<u32>,
+    /// libpq `keepalives_count`: unanswered probes before the connection is
+    /// considered dead
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keepalives_count: Option<u32>,
+    /// Plaintext is expected for this connection; don't warn about it
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub insecure: bool,
+    /// The password is kept unencrypted in `plaintext-passwords.json`
+    /// rather than the keyring (`store --plaintext`, CI only)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub plaintext_password: bool,
+    /// Client to open this connection in (psql, pgcli or usql), ahead of
+    /// the global `driver` setting
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub driver: Option<String>,
+    /// Database engine (postgres, cockroach, redshift or timescale);
+    /// unset means postgres
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub engine: Option<String>,
+    /// Shell command run before connecting, in place of the global
+    /// `pre_connect` setting
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pre_connect: Option<String>,
+    /// Shell command run after psql or the session shell exits, in place
+    /// of the global `post_disconnect` setting
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_disconnect: Option<String>,
+}
+
+impl ConnectionInfo {
+    /// Expand `${VAR}` references in the host, database and username from
+    /// the environment. Done at connect time so the stored value keeps the
+    /// reference.
+    pub fn resolve_env(&self) -> Result<ConnectionInfo> {
+        let lookup = |var: &str| std::env::var(var).ok();
+        Ok(ConnectionInfo {
+            host: interpolate("host", &self.host, lookup)?,
+            replica_host: self
+                .replica_host
+                .as_deref()
+                .map(|host| interpolate("replica_host", host, lookup))
+                .transpose()?,
+            database: interpolate("database", &self.database, lookup)?,
+            username: interpolate("username", &self.username, lookup)?,
+            maintenance_database: self
+                .maintenance_database
+                .as_deref()
+                .map(|database| interpolate("maintenance_database", database, lookup))
+                .transpose()?,
+            ..self.clone()
+        })
+    }
+
+    /// This connection pointed at its read replica instead of the primary.
+    pub fn for_replica(&self, name: &str) -> Result<ConnectionInfo> {
+        let Some(replica_host) = &self.replica_host else {
+            anyhow::bail!(
+                "Connection '{}' has no replica. Add one with 'pg-vault store {} --replica-host <host> --replace'.",
+                name,
+                name
+            );
+        };
+        Ok(ConnectionInfo {
+            host: replica_host.clone(),
+            ..self.clone()
+        })
+    }
+
+    /// Whether connecting opens a bastion tunnel or `cloud-sql-proxy` first.
+    pub fn uses_tunnel(&self) -> bool {
+        self.bastion.is_some() || self.ssh_jump.is_some() || self.cloud_sql_instance.is_some()
+    }
+
+    /// This connection pointed at its maintenance database, falling back to
+    /// `database` when none is set.
+    pub fn for_maintenance(&self) -> ConnectionInfo {
+        let mut info = self.clone();
+        if let Some(database) = &self.maintenance_database {
+            info.database = database.clone();
+        }
+        info
+    }
+}
+
+/// Replace `${VAR}` with its value from `lookup`. `$$` is a literal `$`,
+/// and a `$` followed by anything else is left alone.
+fn interpolate(field: &str, value: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String> {
+    let mut resolved = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('$') {
+        resolved.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        if let Some(after) = after.strip_prefix('$') {
+            resolved.push('$');
+            rest = after;
+        } else if let Some(reference) = after.strip_prefix('{') {
+            let end = reference.find('}').with_context(|| {
+                format!("Unterminated '${{' in {} '{}'; write '$$' for a literal '$'", field, value)
+            })?;
+            let var = &reference[..end];
+            let expanded = lookup(var).ok_or_else(|| {
+                coded(
+                    "env_var_unset",
+                    format!("The {} '{}' uses ${{{}}}, which is not set", field, value, var),
+                )
+            })?;
+            resolved.push_str(&expanded);
+            rest = &reference[end + 1..];
+        } else {
+            resolved.push('$');
+            rest = after;
+        }
+    }
+    resolved.push_str(rest);
+    Ok(resolved)
+}
+
+/// An SSH jump host defined once in settings and shared by any number of
+/// connections via `ConnectionInfo::bastion`.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct BastionProfile {
+    pub host: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity_file: Option<String>,
+}
+
+impl BastionProfile {
+    /// A bastion given inline as `[user@]host[:port]`, as in a
+    /// connection's `ssh_jump`. A user or host that ssh could read as an
+    /// option (`-oProxyCommand=...`) or split on whitespace is rejected.
+    pub fn from_jump(jump: &str) -> Option<BastionProfile> {
+        let (user, rest) = match jump.rsplit_once('@') {
+            Some((user, rest)) => (Some(user), rest),
+            None => (None, jump),
+        };
+        let (host, port) = match rest.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port.parse().ok()?)),
+            None => (rest, None),
+        };
+        let valid = |part: &str| !part.is_empty() && !part.starts_with('-') && !part.contains(char::is_whitespace);
+        if !valid(host) || !user.is_none_or(valid) {
+            return None;
+        }
+        Some(BastionProfile {
+            host: host.to_string(),
+            user: user.map(str::to_string),
+            port,
+            identity_file: None,
+        })
+    }
+}
+
+/// What pressing Enter on a connection in the TUI list does.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DefaultAction {
+    /// Open the actions popup
+    #[default]
+    Menu,
+    /// Connect with psql (IAM connections go through the profile selector)
+    Connect,
+    /// Same as `Connect`, kept for symmetry with the actions popup
+    IamConnect,
+    /// Start a shell session with PostgreSQL environment variables
+    Session,
+}
+
+/// What the TUI's connection list is ordered by. Ties (and `Name`) fall back
+/// to the connection name.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortField {
+    #[default]
+    Name,
+    Host,
+    Database,
+}
+
+impl SortField {
+    pub fn label(self) -> &'static str {
+        match self {
+            SortField::Name => "name",
+            SortField::Host => "host",
+            SortField::Database => "database",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            SortField::Name => SortField::Host,
+            SortField::Host => SortField::Database,
+            SortField::Database => SortField::Name,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct ListSort {
+    #[serde(default)]
+    pub field: SortField,
+    #[serde(default)]
+    pub descending: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct TuiSettings {
+    #[serde(default)]
+    pub default_action: DefaultAction,
+    /// Last sort chosen in the TUI, restored on the next launch
+    #[serde(default)]
+    pub sort: ListSort,
+    /// Quit the TUI after this many minutes without input; off when unset or 0
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_timeout_minutes: Option<u64>,
+}
+
+impl TuiSettings {
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout_minutes
+            .filter(|minutes| *minutes > 0)
+            .map(|minutes| Duration::from_secs(minutes * 60))
+    }
+}
+
+/// Global, non-connection settings stored in `settings.json`.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Settings {
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub bastions: HashMap<String, BastionProfile>,
+    /// Named, ordered lists of connections (e.g. a migration's source then target)
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub workflows: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pub tui: TuiSettings,
+    /// Client for connections that don't set their own `driver`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub driver: Option<String>,
+    /// Shell command run before every connect (e.g. refreshing a VPN), for
+    /// connections that don't set their own `pre_connect`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pre_connect: Option<String>,
+    /// Shell command run after every psql or session shell exits, for
+    /// connections that don't set their own `post_disconnect`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_disconnect: Option<String>,
+    /// Warn before connecting when a connection's TLS certificate expires
+    /// within this many days (default 30)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cert_expiry_warning_days: Option<u32>,
+}
+
+/// A `connect`, `session` or `iam` command as it was typed, so `last` can
+/// run it again.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LastAction {
+    /// Command-line arguments after the program name
+    pub args: Vec<String>,
+    /// The connection it opened, if it named one (`session --tag` doesn't)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connection: Option<String>,
+}
+
+/// Why the most recent attempt to connect failed, kept so a recurring
+/// problem is visible before trying again.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LastError {
+    pub message: String,
+    /// Seconds since the Unix epoch
+    pub at: u64,
+}
+
+impl LastError {
+    /// Any connection URL in `message` has its password masked, since the
+    /// message is written to disk and shown in the TUI.
+    pub fn now(message: impl Into<String>) -> Self {
+        LastError {
+            message: redact(&message.into()),
+            at: unix_now(),
+        }
+    }
+
+    /// How long ago the failure happened, e.g. `3h ago`.
+    pub fn age(&self) -> String {
+        age_since(self.at)
+    }
+}
+
+/// How long ago a Unix timestamp was, e.g. `3h ago`.
+pub fn age_since(at: u64) -> String {
+    let secs = unix_now().saturating_sub(at);
+    match secs {
+        0..60 => "just now".to_string(),
+        60..3600 => format!("{}m ago", secs / 60),
+        3600..86400 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+/// A length of time at the two largest units, e.g. `12m3s` or `2h5m`.
+pub fn format_duration(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m{}s", secs / 60, secs % 60),
+        _ => format!("{}h{}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// Environment variable naming a config directory to use instead of the
+/// default, like `--config-dir`.
+pub const CONFIG_DIR_ENV: &str = "PG_VAULT_CONFIG_DIR";
+
+static CONFIG_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Use `dir` for everything pg-vault stores (`--config-dir`). Takes
+/// precedence over `PG_VAULT_CONFIG_DIR`; only the first call counts.
+pub fn set_config_dir(dir: PathBuf) {
+    let _ = CONFIG_DIR.set(dir);
+}
+
+pub fn get_config_dir() -> Result<PathBuf> {
+    let app_dir = match CONFIG_DIR.get() {
+        Some(dir) => dir.clone(),
+        None => match std::env::var_os(CONFIG_DIR_ENV).filter(|dir| !dir.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => dirs::config_dir()
+                .context("Could not find config directory")?
+                .join("pg-vault"),
+        },
+    };
+    // Only created when missing, so an existing read-only directory still
+    // works for commands that just read it
+    if !app_dir.is_dir() {
+        fs::create_dir_all(&app_dir)
+            .map_err(|e| config_io_error(e, "create the config directory", &app_dir))?;
+    }
+    Ok(app_dir)
+}
+
+/// Write a file in the config directory through a temporary file renamed
+/// over it, so an interrupted write or a concurrent run never leaves it
+/// truncated. A symlinked file (e.g. from a dotfiles repo) is written
+/// through, not replaced.
+pub fn write_config_file(path: &Path, content: &str, what: &str) -> Result<()> {
+    let target = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    // Named per process, so two runs saving at once don't share it
+    let mut temp_name = target.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(format!(".{}.tmp", std::process::id()));
+    let temp = target.with_file_name(temp_name);
+
+    let written = fs::File::create(&temp)
+        .and_then(|mut file| {
+            io::Write::write_all(&mut file, content.as_bytes())?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&temp, &target));
+    written.map_err(|e| {
+        let _ = fs::remove_file(&temp);
+        config_io_error(e, &format!("write {}", what), path)
+    })
+}
+
+/// Turn a failure to create or write something under the config directory
+/// into an error that says how to get past it when the directory is
+/// read-only or not ours.
+pub fn config_io_error(err: io::Error, action: &str, path: &Path) -> anyhow::Error {
+    match err.kind() {
+        io::ErrorKind::ReadOnlyFilesystem | io::ErrorKind::PermissionDenied => coded(
+            "config_not_writable",
+            format!(
+                "Could not {} at {}: {}. Check that you can write to it, or point pg-vault at a \
+writable directory with --config-dir or {}.",
+                action,
+                path.display(),
+                err,
+                CONFIG_DIR_ENV
+            ),
+        ),
+        _ => anyhow::Error::from(err).context(format!("Could not {}", action)),
+    }
 }
 
 pub fn get_config_path() -> Result<PathBuf> {
-    let config_dir = dirs::config_dir().context("Could not find config directory")?;
-    let app_dir = config_dir.join("pg-vault");
-    fs::create_dir_all(&app_dir).context("Could not create config directory")?;
-    Ok(app_dir.join("connections.json"))
+    Ok(get_config_dir()?.join("connections.json"))
 }
 
+pub fn get_settings_path() -> Result<PathBuf> {
+    Ok(get_config_dir()?.join("settings.json"))
+}
+
+/// Last connect failure per connection. Kept apart from `connections.json`
+/// so that file stays free of machine-local state.
+pub fn get_last_errors_path() -> Result<PathBuf> {
+    Ok(get_config_dir()?.join("last-errors.json"))
+}
+
+/// When each connection last connected successfully, in seconds since the
+/// Unix epoch.
+pub fn get_last_used_path() -> Result<PathBuf> {
+    Ok(get_config_dir()?.join("last-used.json"))
+}
+
+/// Total seconds spent in psql or session shells, per connection.
+pub fn get_session_time_path() -> Result<PathBuf> {
+    Ok(get_config_dir()?.join("session-time.json"))
+}
+
+/// The most recent `connect`, `session` or `iam` command, for `last`.
+pub fn get_last_action_path() -> Result<PathBuf> {
+    Ok(get_config_dir()?.join("last-action.json"))
+}
+
+/// Directory of extra `*.json` connection files (e.g. shared team
+/// definitions checked out from version control).
+pub fn get_drop_in_dir() -> Result<PathBuf> {
+    Ok(get_config_dir()?.join("connections.d"))
+}
+
+/// A connection defined in `connections.d/`, with the file it came from.
+pub struct DropInConnection {
+    pub path: PathBuf,
+    pub info: ConnectionInfo,
+}
+
+/// Read every `*.json` file in `dir` in name order. When two files define
+/// the same connection, the later file wins.
+fn load_drop_ins(dir: &Path) -> Result<HashMap<String, DropInConnection>> {
+    let mut drop_ins = HashMap::new();
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Ok(drop_ins);
+    };
+
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+
+    for path in paths {
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Could not read {}", path.display()))?;
+        let connections = parse_connections(&content)
+            .with_context(|| format!("Could not parse {}", path.display()))?;
+        for (name, info) in connections {
+            drop_ins.insert(
+                name,
+                DropInConnection {
+                    path: path.clone(),
+                    info,
+                },
+            );
+        }
+    }
+    Ok(drop_ins)
+}
+
+/// Parse a connections file (`{"name": {...}, ...}`), checking each entry
+/// so a hand-edited file gets errors naming the connection and field
+/// instead of a bare serde message. Every problem found is reported.
+pub fn parse_connections(content: &str) -> Result<HashMap<String, ConnectionInfo>> {
+    check_conflict_markers(content)?;
+    let value: serde_json::Value = serde_json::from_str(content).context("Invalid JSON")?;
+    let serde_json::Value::Object(entries) = value else {
+        anyhow::bail!("Expected an object mapping connection names to connections");
+    };
+
+    let mut connections = HashMap::new();
+    let mut problems = Vec::new();
+    for (name, entry) in entries {
+        match parse_connection(entry) {
+            Ok(info) => {
+                connections.insert(name, info);
+            }
+            Err(problem) => problems.push(format!("connection '{}': {}", name, problem)),
+        }
+    }
+
+    if !problems.is_empty() {
+        problems.sort();
+        anyhow::bail!(problems.join("\n"));
+    }
+    Ok(connections)
+}
+
+/// Connections as `export` writes them: the `connections.json` shape,
+/// sorted by name, with a `"password"` added to each connection that has
+/// one in `passwords`.
+pub fn export_connections(
+    connections: &HashMap<String, ConnectionInfo>,
+    passwords: &HashMap<String, Secret>,
+) -> Result<String> {
+    let mut exported = BTreeMap::new();
+    for (name, info) in connections {
+        let mut entry = serde_json::to_value(info).context("Could not serialize connection")?;
+        if let (Some(password), Some(fields)) = (passwords.get(name), entry.as_object_mut()) {
+            fields.insert("password".to_string(), password.expose().into());
+        }
+        exported.insert(name, entry);
+    }
+    serde_json::to_string_pretty(&exported).context("Could not serialize connections")
+}
+
+/// Read an `export`, checked like `connections.json`, returning the
+/// connections and any passwords it included.
+pub fn parse_export(content: &str) -> Result<(HashMap<String, ConnectionInfo>, HashMap<String, Secret>)> {
+    let mut value: serde_json::Value = serde_json::from_str(content).context("Invalid JSON")?;
+    let mut passwords = HashMap::new();
+    if let Some(entries) = value.as_object_mut() {
+        for (name, entry) in entries.iter_mut() {
+            let Some(fields) = entry.as_object_mut() else {
+                continue;
+            };
+            match fields.remove("password") {
+                Some(serde_json::Value::String(password)) => {
+                    passwords.insert(name.clone(), Secret::new(password));
+                }
+                Some(_) => anyhow::bail!("connection '{}': 'password' must be a string", name),
+                None => {}
+            }
+        }
+    }
+    let connections = parse_connections(&value.to_string())?;
+    Ok((connections, passwords))
+}
+
+fn parse_connection(entry: serde_json::Value) -> std::result::Result<ConnectionInfo, String> {
+    let Some(fields) = entry.as_object() else {
+        return Err("expected an object".to_string());
+    };
+
+    for field in ["host", "database", "username"] {
+        match fields.get(field) {
+            None => return Err(format!("missing required field '{}'", field)),
+            Some(serde_json::Value::String(value)) if !value.trim().is_empty() => {}
+            Some(_) => return Err(format!("'{}' must be a non-empty string", field)),
+        }
+    }
+    match fields.get("port") {
+        None => return Err("missing required field 'port'".to_string()),
+        Some(port) if port.as_u64().is_some_and(|port| (1..=65535).contains(&port)) => {}
+        Some(_) => return Err("'port' must be a number between 1 and 65535".to_string()),
+    }
+
+    // Required fields are known good, so serde only reports optional ones
+    let info: ConnectionInfo = serde_json::from_value(entry).map_err(|e| e.to_string())?;
+
+    if let Some(sslmode) = &info.sslmode
+        && !SSLMODES.contains(&sslmode.as_str())
+    {
+        return Err(format!(
+            "'sslmode' must be one of {}, not '{}'",
+            SSLMODES.join(", "),
+            sslmode
+        ));
+    }
+    if let Some(channel_binding) = &info.channel_binding {
+        if !CHANNEL_BINDINGS.contains(&channel_binding.as_str()) {
+            return Err(format!(
+                "'channel_binding' must be one of {}, not '{}'",
+                CHANNEL_BINDINGS.join(", "),
+                channel_binding
+            ));
+        }
+        if let Some(problem) = channel_binding_conflict(&info) {
+            return Err(problem.to_string());
+        }
+    }
+    if let Some(driver) = &info.driver
+        && !DRIVERS.contains(&driver.as_str())
+    {
+        return Err(format!(
+            "'driver' must be one of {}, not '{}'",
+            DRIVERS.join(", "),
+            driver
+        ));
+    }
+    if let Some(engine) = &info.engine {
+        if !ENGINES.contains(&engine.as_str()) {
+            return Err(format!(
+                "'engine' must be one of {}, not '{}'",
+                ENGINES.join(", "),
+                engine
+            ));
+        }
+        if info.iam_auth && !engine_profile(Some(engine)).supports_iam {
+            return Err(format!("'iam_auth' isn't supported for engine '{}'", engine));
+        }
+    }
+    if info.cloud_sql_instance.is_some() && (info.iam_auth || info.bastion.is_some()) {
+        return Err("'cloud_sql_instance' can't be combined with 'iam_auth' or 'bastion'".to_string());
+    }
+    if let Some(jump) = &info.ssh_jump {
+        if info.bastion.is_some() || info.cloud_sql_instance.is_some() {
+            return Err("'ssh_jump' can't be combined with 'bastion' or 'cloud_sql_instance'".to_string());
+        }
+        if BastionProfile::from_jump(jump).is_none() {
+            return Err(format!("'ssh_jump' must look like [user@]host[:port], not '{}'", jump));
+        }
+    }
+    if info.local_port.is_some() && !info.uses_tunnel() {
+        return Err("'local_port' is only used with 'bastion', 'ssh_jump' or 'cloud_sql_instance'".to_string());
+    }
+    if info.region.is_some() && !info.iam_auth {
+        return Err("'region' is only used with 'iam_auth'".to_string());
+    }
+    if info.confirm_phrase.is_some() && !info.protected {
+        return Err("'confirm_phrase' is only used when 'protected' is true".to_string());
+    }
+    Ok(info)
+}
+
+/// Find the `connections.d/` file a connection is defined in, if any.
+pub fn drop_in_source(name: &str) -> Result<Option<PathBuf>> {
+    Ok(load_drop_ins(&get_drop_in_dir()?)?
+        .remove(name)
+        .map(|drop_in| drop_in.path))
+}
+
+/// Why `info`'s `channel_binding` can't work, if it can't. IAM tokens are
+/// sent as plain passwords rather than through SCRAM, and binding is done
+/// over TLS.
+pub fn channel_binding_conflict(info: &ConnectionInfo) -> Option<&'static str> {
+    match info.channel_binding.as_deref() {
+        Some(_) if info.iam_auth => Some("'channel_binding' can't be used with 'iam_auth', which doesn't use SCRAM"),
+        Some("require") if info.sslmode.as_deref() == Some("disable") => {
+            Some("'channel_binding' require needs TLS, so it can't be combined with sslmode disable")
+        }
+        _ => None,
+    }
+}
+
+/// Files synced through git can be left mid-merge, which serde would only
+/// report as a syntax error somewhere near the first marker.
+fn check_conflict_markers(content: &str) -> Result<()> {
+    let is_marker = |line: &str| {
+        line.starts_with("<<<<<<<") || line.starts_with(">>>>>>>") || line.trim_end() == "======="
+    };
+    let Some(line) = content.lines().position(is_marker) else {
+        return Ok(());
+    };
+    Err(coded(
+        "merge_conflict",
+        format!(
+            "line {} has a git merge conflict marker. Keep one side of each \
+<<<<<<< ... >>>>>>> block and delete the marker lines, then try again.",
+            line + 1
+        ),
+    ))
+}
+
+/// Load `connections.json` merged over the files in `connections.d/`; an
+/// entry in the primary file overrides a drop-in with the same name.
 pub fn load_connections() -> Result<HashMap<String, ConnectionInfo>> {
+    let mut connections: HashMap<String, ConnectionInfo> = load_drop_ins(&get_drop_in_dir()?)?
+        .into_iter()
+        .map(|(name, drop_in)| (name, drop_in.info))
+        .collect();
+
     let config_path = get_config_path()?;
     if !config_path.exists() {
-        return Ok(HashMap::new());
+        return Ok(connections);
     }
 
     let content = fs::read_to_string(config_path).context("Could not read connections file")?;
-    let connections: HashMap<String, ConnectionInfo> =
-        serde_json::from_str(&content).context("Could not parse connections file")?;
+    let primary = parse_connections(&content).context("Could not parse connections file")?;
+    connections.extend(primary);
     Ok(connections)
 }
 
+/// Entries that belong in the primary file: anything not defined in a
+/// drop-in, plus local overrides that differ from their drop-in.
+fn primary_entries<'a>(
+    connections: &'a HashMap<String, ConnectionInfo>,
+    drop_ins: &HashMap<String, DropInConnection>,
+) -> BTreeMap<&'a String, &'a ConnectionInfo> {
+    connections
+        .iter()
+        .filter(|(name, info)| {
+            drop_ins
+                .get(*name)
+                .is_none_or(|drop_in| drop_in.info != **info)
+        })
+        .collect()
+}
+
+/// Held while a command reads, changes and saves the connections or one of
+/// the other files in the config directory, so two runs at once can't each
+/// save over the other's change.
+const LOCK_FILE: &str = "connections.json.lock";
+
+/// How long to wait for another run to finish with the connections.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A lock file this old whose holder can't be checked (no PID in it, or
+/// no way to look the PID up) was left by a run that died holding it.
+const STALE_LOCK: Duration = Duration::from_secs(60);
+
+/// An advisory lock on the config files, taken by creating a lock file next
+/// to the connections and released (the file removed) when dropped.
+pub struct ConfigLock {
+    path: PathBuf,
+}
+
+impl ConfigLock {
+    pub fn acquire(path: &Path) -> Result<Self> {
+        let started = Instant::now();
+        loop {
+            match fs::OpenOptions::new().write(true).create_new(true).open(path) {
+                Ok(mut file) => {
+                    // So a waiter can tell whether the holder is still running
+                    let _ = io::Write::write_all(&mut file, format!("{}\n", std::process::id()).as_bytes());
+                    return Ok(ConfigLock {
+                        path: path.to_path_buf(),
+                    });
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    let holder = LockHolder::read(path);
+                    if holder.as_ref().is_some_and(LockHolder::is_abandoned) {
+                        // Only if it's still the lock judged abandoned, not
+                        // one another waiter has since broken and taken
+                        if LockHolder::read(path) == holder {
+                            let _ = fs::remove_file(path);
+                        }
+                    } else if started.elapsed() > LOCK_TIMEOUT {
+                        return Err(coded(
+                            "config_locked",
+                            format!(
+                                "Another pg-vault is still changing the config files. If none is running, \
+delete {} and try again.",
+                                path.display()
+                            ),
+                        ));
+                    } else {
+                        std::thread::sleep(Duration::from_millis(50));
+                    }
+                }
+                Err(e) => return Err(config_io_error(e, "lock the connections file", path)),
+            }
+        }
+    }
+}
+
+impl Drop for ConfigLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Who holds a lock file, as seen by a run waiting for it.
+#[derive(Debug, PartialEq)]
+struct LockHolder {
+    /// Missing if the holder hasn't written it yet (or the file is foreign)
+    pid: Option<u32>,
+    modified: SystemTime,
+}
+
+impl LockHolder {
+    fn read(path: &Path) -> Option<LockHolder> {
+        let modified = fs::metadata(path).and_then(|metadata| metadata.modified()).ok()?;
+        let pid = fs::read_to_string(path).ok()?.trim().parse().ok();
+        Some(LockHolder { pid, modified })
+    }
+
+    /// Whether the run holding the lock has gone without releasing it. A
+    /// live holder keeps its lock however long it takes.
+    fn is_abandoned(&self) -> bool {
+        match self.pid.and_then(process_is_running) {
+            Some(running) => !running,
+            None => self
+                .modified
+                .elapsed()
+                .is_ok_and(|age| age > STALE_LOCK),
+        }
+    }
+}
+
+/// Whether a process with this PID exists, if that can be checked here.
+#[cfg(unix)]
+fn process_is_running(pid: u32) -> Option<bool> {
+    let pid = libc::pid_t::try_from(pid).ok()?;
+    // Signal 0 checks the process exists without sending anything
+    if unsafe { libc::kill(pid, 0) } == 0 {
+        return Some(true);
+    }
+    // EPERM: it exists but belongs to someone else
+    Some(io::Error::last_os_error().raw_os_error() == Some(libc::EPERM))
+}
+
+#[cfg(not(unix))]
+fn process_is_running(_pid: u32) -> Option<bool> {
+    None
+}
+
+/// Run `f` holding the lock on the connections. Not reentrant: `f` must not
+/// take it again.
+pub fn with_config_lock<T>(f: impl FnOnce() -> Result<T>) -> Result<T> {
+    let _lock = ConfigLock::acquire(&get_config_dir()?.join(LOCK_FILE))?;
+    f()
+}
+
+/// Load the connections, change them with `update` and save them, all
+/// under the lock, so a change made by another run in the meantime is kept.
+pub fn update_connections(update: impl FnOnce(&mut HashMap<String, ConnectionInfo>) -> Result<()>) -> Result<()> {
+    with_config_lock(|| {
+        let mut connections = load_connections()?;
+        update(&mut connections)?;
+        save_connections(&connections)
+    })
+}
+
+/// Save connections to `connections.json`. Entries that come unchanged from
+/// `connections.d/` are left in their own files.
 pub fn save_connections(connections: &HashMap<String, ConnectionInfo>) -> Result<()> {
     let config_path = get_config_path()?;
+    let drop_ins = load_drop_ins(&get_drop_in_dir()?)?;
+    // Write entries sorted by name so the file diffs cleanly under version control
+    let sorted = primary_entries(connections, &drop_ins);
+    let content =
+        serde_json::to_string_pretty(&sorted).context("Could not serialize connections")?;
+    write_config_file(&config_path, &content, "connections file")?;
+    Ok(())
+}
+
+pub fn find_connection<'a>(
+    connections: &'a HashMap<String, ConnectionInfo>,
+    name: &str,
+) -> Result<&'a ConnectionInfo> {
+    connections.get(name).ok_or_else(|| {
+        coded(
+            "connection_not_found",
+            format!("Connection '{}' not found", name),
+        )
+    })
+}
+
+/// Look up a connection with its `${VAR}` references expanded, ready to
+/// connect with.
+pub fn find_resolved_connection(
+    connections: &HashMap<String, ConnectionInfo>,
+    name: &str,
+) -> Result<ConnectionInfo> {
+    find_connection(connections, name)?.resolve_env()
+}
+
+pub fn load_settings() -> Result<Settings> {
+    let settings_path = get_settings_path()?;
+    if !settings_path.exists() {
+        return Ok(Settings::default());
+    }
+
+    let content = fs::read_to_string(settings_path).context("Could not read settings file")?;
+    check_conflict_markers(&content).context("Could not parse settings file")?;
+    let settings: Settings =
+        serde_json::from_str(&content).context("Could not parse settings file")?;
+    Ok(settings)
+}
+
+/// Load the settings, change them with `update` and save them under the
+/// lock, as `update_connections` does for the connections.
+pub fn update_settings(update: impl FnOnce(&mut Settings) -> Result<()>) -> Result<()> {
+    with_config_lock(|| {
+        let mut settings = load_settings()?;
+        update(&mut settings)?;
+        save_settings(&settings)
+    })
+}
+
+fn save_settings(settings: &Settings) -> Result<()> {
+    let settings_path = get_settings_path()?;
+    let content = serde_json::to_string_pretty(settings).context("Could not serialize settings")?;
+    write_config_file(&settings_path, &content, "settings file")?;
+    Ok(())
+}
+
+pub fn load_last_errors() -> Result<HashMap<String, LastError>> {
+    let path = get_last_errors_path()?;
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+
+    let content = fs::read_to_string(path).context("Could not read last errors file")?;
+    serde_json::from_str(&content).context("Could not parse last errors file")
+}
+
+fn save_last_errors(errors: &HashMap<String, LastError>) -> Result<()> {
+    let sorted: BTreeMap<_, _> = errors.iter().collect();
     let content =
-        serde_json::to_string_pretty(connections).context("Could not serialize connections")?;
-    fs::write(config_path, content).context("Could not write connections file")?;
+        serde_json::to_string_pretty(&sorted).context("Could not serialize last errors")?;
+    write_config_file(&get_last_errors_path()?, &content, "last errors file")?;
+    Ok(())
+}
+
+/// Remember `error` as the latest failure for `name`.
+pub fn record_last_error(name: &str, error: &LastError) -> Result<()> {
+    with_config_lock(|| {
+        let mut errors = load_last_errors()?;
+        errors.insert(name.to_string(), error.clone());
+        save_last_errors(&errors)
+    })
+}
+
+/// Forget the failure recorded for `name`, e.g. once it connects again.
+pub fn clear_last_error(name: &str) -> Result<()> {
+    with_config_lock(|| {
+        let mut errors = load_last_errors()?;
+        if errors.remove(name).is_some() {
+            save_last_errors(&errors)?;
+        }
+        Ok(())
+    })
+}
+
+pub fn load_last_used() -> Result<HashMap<String, u64>> {
+    let path = get_last_used_path()?;
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+
+    let content = fs::read_to_string(path).context("Could not read last used file")?;
+    serde_json::from_str(&content).context("Could not parse last used file")
+}
+
+/// Remember that `name` just connected successfully.
+pub fn record_last_used(name: &str) -> Result<()> {
+    with_config_lock(|| {
+        let mut last_used = load_last_used()?;
+        last_used.insert(name.to_string(), unix_now());
+        let sorted: BTreeMap<_, _> = last_used.iter().collect();
+        let content = serde_json::to_string_pretty(&sorted).context("Could not serialize last used")?;
+        write_config_file(&get_last_used_path()?, &content, "last used file")
+    })
+}
+
+pub fn load_session_time() -> Result<HashMap<String, u64>> {
+    let path = get_session_time_path()?;
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+
+    let content = fs::read_to_string(path).context("Could not read session time file")?;
+    serde_json::from_str(&content).context("Could not parse session time file")
+}
+
+/// Add a finished session's length to `name`'s total.
+pub fn record_session_time(name: &str, secs: u64) -> Result<()> {
+    with_config_lock(|| {
+        let mut totals = load_session_time()?;
+        *totals.entry(name.to_string()).or_default() += secs;
+        let sorted: BTreeMap<_, _> = totals.iter().collect();
+        let content = serde_json::to_string_pretty(&sorted).context("Could not serialize session time")?;
+        write_config_file(&get_session_time_path()?, &content, "session time file")
+    })
+}
+
+pub fn load_last_action() -> Result<Option<LastAction>> {
+    let path = get_last_action_path()?;
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let content = fs::read_to_string(path).context("Could not read last action file")?;
+    serde_json::from_str(&content).context("Could not parse last action file")
+}
+
+pub fn save_last_action(action: &LastAction) -> Result<()> {
+    let content = serde_json::to_string_pretty(action).context("Could not serialize last action")?;
+    write_config_file(&get_last_action_path()?, &content, "last action file")?;
     Ok(())
 }
+
+pub fn clear_last_action() -> Result<()> {
+    let path = get_last_action_path()?;
+    if path.exists() {
+        fs::remove_file(&path).map_err(|e| config_io_error(e, "remove", &path))?;
+    }
+    Ok(())
+}
+
+/// Up to `count` of `connections`, most recently used first. Entries for
+/// connections that no longer exist are skipped.
+pub fn most_recent<'a>(
+    last_used: &HashMap<String, u64>,
+    connections: &'a HashMap<String, ConnectionInfo>,
+    count: usize,
+) -> Vec<(&'a String, u64)> {
+    let mut recent: Vec<(&String, u64)> = connections
+        .keys()
+        .filter_map(|name| last_used.get(name).map(|at| (name, *at)))
+        .collect();
+    recent.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+    recent.truncate(count);
+    recent
+}
+
+/// Where connections and what's remembered about them are kept. The
+/// commands that manage connections (`store`, `edit`, `list`, `show`,
+/// `remove`, `rename`, `export`, `import`, `pgpass`, `tidy`) take one of
+/// these so tests can run them against `memory::MemoryConfig`. Commands that
+/// open a session, or only change settings, still use the free functions.
+pub trait ConfigStore {
+    fn load_connections(&self) -> Result<HashMap<String, ConnectionInfo>>;
+
+    fn save_connections(&self, connections: &HashMap<String, ConnectionInfo>) -> Result<()>;
+
+    /// Load, change and save the connections as one step that another run
+    /// can't interleave with.
+    fn update_connections(&self, update: &mut dyn FnMut(&mut HashMap<String, ConnectionInfo>) -> Result<()>) -> Result<()> {
+        let mut connections = self.load_connections()?;
+        update(&mut connections)?;
+        self.save_connections(&connections)
+    }
+
+    /// The `connections.d/` file `name` is defined in, if any.
+    fn drop_in_source(&self, name: &str) -> Result<Option<PathBuf>>;
+
+    fn load_last_errors(&self) -> Result<HashMap<String, LastError>>;
+
+    fn clear_last_error(&self, name: &str) -> Result<()>;
+}
+
+/// The files in the config directory.
+pub struct FileConfig;
+
+impl ConfigStore for FileConfig {
+    fn load_connections(&self) -> Result<HashMap<String, ConnectionInfo>> {
+        load_connections()
+    }
+
+    fn save_connections(&self, connections: &HashMap<String, ConnectionInfo>) -> Result<()> {
+        save_connections(connections)
+    }
+
+    fn update_connections(&self, update: &mut dyn FnMut(&mut HashMap<String, ConnectionInfo>) -> Result<()>) -> Result<()> {
+        update_connections(update)
+    }
+
+    fn drop_in_source(&self, name: &str) -> Result<Option<PathBuf>> {
+        drop_in_source(name)
+    }
+
+    fn load_last_errors(&self) -> Result<HashMap<String, LastError>> {
+        load_last_errors()
+    }
+
+    fn clear_last_error(&self, name: &str) -> Result<()> {
+        clear_last_error(name)
+    }
+}
+
+#[cfg(test)]
+pub mod memory {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// Keeps everything in memory. Names in `drop_ins` behave as if they
+    /// came from that file in `connections.d/`.
+    #[derive(Default)]
+    pub struct MemoryConfig {
+        pub connections: RefCell<HashMap<String, ConnectionInfo>>,
+        pub drop_ins: HashMap<String, PathBuf>,
+        pub last_errors: RefCell<HashMap<String, LastError>>,
+    }
+
+    impl MemoryConfig {
+        pub fn with(connections: &[(&str, ConnectionInfo)]) -> Self {
+            MemoryConfig {
+                connections: RefCell::new(
+                    connections
+                        .iter()
+                        .map(|(name, info)| (name.to_string(), info.clone()))
+                        .collect(),
+                ),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl ConfigStore for MemoryConfig {
+        fn load_connections(&self) -> Result<HashMap<String, ConnectionInfo>> {
+            Ok(self.connections.borrow().clone())
+        }
+
+        fn save_connections(&self, connections: &HashMap<String, ConnectionInfo>) -> Result<()> {
+            *self.connections.borrow_mut() = connections.clone();
+            Ok(())
+        }
+
+        fn drop_in_source(&self, name: &str) -> Result<Option<PathBuf>> {
+            Ok(self.drop_ins.get(name).cloned())
+        }
+
+        fn load_last_errors(&self) -> Result<HashMap<String, LastError>> {
+            Ok(self.last_errors.borrow().clone())
+        }
+
+        fn clear_last_error(&self, name: &str) -> Result<()> {
+            self.last_errors.borrow_mut().remove(name);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::error_code;
+
+    fn info(host: &str) -> ConnectionInfo {
+        ConnectionInfo {
+            host: host.to_string(),
+            port: 5432,
+            database: "app".to_string(),
+            username: "app".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn channel_binding_is_checked_against_auth_and_tls() {
+        let binding = |mode: &str| ConnectionInfo {
+            channel_binding: Some(mode.to_string()),
+            ..info("db")
+        };
+        assert_eq!(channel_binding_conflict(&binding("require")), None);
+
+        let iam = ConnectionInfo { iam_auth: true, ..binding("prefer") };
+        assert!(channel_binding_conflict(&iam).unwrap().contains("iam_auth"));
+
+        let no_tls = ConnectionInfo {
+            sslmode: Some("disable".to_string()),
+            ..binding("require")
+        };
+        assert!(channel_binding_conflict(&no_tls).is_some());
+        let no_tls = ConnectionInfo { channel_binding: Some("prefer".to_string()), ..no_tls };
+        assert_eq!(channel_binding_conflict(&no_tls), None);
+    }
+
+    #[test]
+    fn exports_round_trip_with_their_passwords() {
+        let connections = HashMap::from([
+            ("app".to_string(), info("db")),
+            (
+                "iam".to_string(),
+                ConnectionInfo {
+                    iam_auth: true,
+                    ..info("rds")
+                },
+            ),
+        ]);
+        let passwords = HashMap::from([("app".to_string(), Secret::new("p@ss \"word\""))]);
+
+        let exported = export_connections(&connections, &passwords).unwrap();
+        assert!(exported.find("\"app\"").unwrap() < exported.find("\"iam\"").unwrap());
+        assert_eq!(parse_export(&exported).unwrap(), (connections, passwords));
+
+        let err = parse_export(r#"{"app": {"host": "a", "port": 5432, "database": "d", "username": "u", "password": 1}}"#);
+        assert!(err.unwrap_err().to_string().contains("'password' must be a string"));
+    }
+
+    #[test]
+    fn malformed_connections_are_reported_by_name_and_field() {
+        let err = parse_connections(
+            r#"{"ok": {"host": "a", "port": 5432, "database": "app", "username": "app"},
+                "no-host": {"port": 5432, "database": "app", "username": "app"},
+                "bad-port": {"host": "a", "port": 70000, "database": "app", "username": "app"},
+                "bad-tags": {"host": "a", "port": 5432, "database": "app", "username": "app", "tags": "prod"},
+                "bad-ssl": {"host": "a", "port": 5432, "database": "app", "username": "app", "sslmode": "on"},
+                "bad-driver": {"host": "a", "port": 5432, "database": "app", "username": "app", "driver": "dbeaver"},
+                "bad-engine": {"host": "a", "port": 5432, "database": "app", "username": "app", "engine": "mysql"},
+                "iam-redshift": {"host": "a", "port": 5439, "database": "app", "username": "app", "iam_auth": true, "engine": "redshift"},
+                "lonely-port": {"host": "a", "port": 5432, "database": "app", "username": "app", "local_port": 15432},
+                "password-region": {"host": "a", "port": 5432, "database": "app", "username": "app", "region": "eu-west-1"}}"#,
+        )
+        .unwrap_err()
+        .to_string();
+
+        let lines: Vec<&str> = err.lines().collect();
+        assert_eq!(lines.len(), 9);
+        assert_eq!(lines[0], "connection 'bad-driver': 'driver' must be one of psql, pgcli, usql, not 'dbeaver'");
+        assert_eq!(
+            lines[1],
+            "connection 'bad-engine': 'engine' must be one of postgres, cockroach, redshift, timescale, not 'mysql'"
+        );
+        assert_eq!(lines[2], "connection 'bad-port': 'port' must be a number between 1 and 65535");
+        assert!(lines[3].starts_with("connection 'bad-ssl': 'sslmode' must be one of"));
+        assert!(lines[4].starts_with("connection 'bad-tags': invalid type: string \"prod\""));
+        assert_eq!(lines[5], "connection 'iam-redshift': 'iam_auth' isn't supported for engine 'redshift'");
+        assert_eq!(
+            lines[6],
+            "connection 'lonely-port': 'local_port' is only used with 'bastion', 'ssh_jump' or 'cloud_sql_instance'"
+        );
+        assert_eq!(lines[7], "connection 'no-host': missing required field 'host'");
+        assert_eq!(lines[8], "connection 'password-region': 'region' is only used with 'iam_auth'");
+
+        assert!(parse_connections("[]").is_err());
+        assert!(parse_connections(r#"{"x": 1}"#).unwrap_err().to_string().contains("expected an object"));
+    }
+
+    #[test]
+    fn ssh_jumps_parse_like_ssh_destinations() {
+        let jump = BastionProfile::from_jump("ec2-user@bastion.example.com:2222").unwrap();
+        assert_eq!(jump.user.as_deref(), Some("ec2-user"));
+        assert_eq!(jump.host, "bastion.example.com");
+        assert_eq!(jump.port, Some(2222));
+
+        let jump = BastionProfile::from_jump("bastion").unwrap();
+        assert_eq!((jump.user, jump.host.as_str(), jump.port), (None, "bastion", None));
+
+        for bad in ["", "@bastion", "user@", "bastion:ssh", "-oProxyCommand=touch /tmp/x", "-luser@bastion", "ec2 user@bastion"] {
+            assert!(BastionProfile::from_jump(bad).is_none(), "{}", bad);
+        }
+        let err = parse_connections(
+            r#"{"app": {"host": "a", "port": 5432, "database": "app", "username": "app", "ssh_jump": "bastion", "bastion": "shared"}}"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("'ssh_jump' can't be combined with 'bastion'"));
+    }
+
+    #[test]
+    fn concurrent_updates_under_the_lock_both_survive() {
+        // Points every config path in this test run at a scratch directory
+        set_config_dir(std::env::temp_dir().join(format!("pg-vault-lock-{}", std::process::id())));
+        let dir = get_config_dir().unwrap();
+        save_connections(&HashMap::new()).unwrap();
+
+        let threads: Vec<_> = ["first", "second"]
+            .into_iter()
+            .map(|name| {
+                std::thread::spawn(move || {
+                    update_connections(|connections| {
+                        // Long enough for the other thread to try to get in
+                        std::thread::sleep(Duration::from_millis(100));
+                        connections.insert(name.to_string(), info(&format!("{}.internal", name)));
+                        Ok(())
+                    })
+                    .unwrap();
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let connections = load_connections().unwrap();
+        assert_eq!(connections["first"], info("first.internal"));
+        assert_eq!(connections["second"], info("second.internal"));
+        assert!(!dir.join(LOCK_FILE).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn locks_are_only_taken_over_from_holders_that_are_gone() {
+        let dir = std::env::temp_dir().join(format!("pg-vault-stale-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let lock = dir.join(LOCK_FILE);
+
+        // A live holder keeps its lock however old it is
+        let live = LockHolder {
+            pid: Some(std::process::id()),
+            modified: UNIX_EPOCH,
+        };
+        assert!(!live.is_abandoned());
+
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        child.wait().unwrap();
+        fs::write(&lock, format!("{}\n", child.id())).unwrap();
+        let started = Instant::now();
+        drop(ConfigLock::acquire(&lock).unwrap());
+        assert!(started.elapsed() < LOCK_TIMEOUT);
+        assert!(!lock.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn config_files_are_replaced_whole_or_not_at_all() {
+        let dir = std::env::temp_dir().join(format!("pg-vault-atomic-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let leftovers = || {
+            fs::read_dir(&dir)
+                .unwrap()
+                .filter(|entry| entry.as_ref().unwrap().file_name().to_string_lossy().ends_with(".tmp"))
+                .count()
+        };
+
+        let path = dir.join("connections.json");
+        write_config_file(&path, "{\"old\": 1}", "connections file").unwrap();
+        write_config_file(&path, "{}", "connections file").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{}");
+        assert_eq!(leftovers(), 0);
+
+        // The rename fails, so what was there stays and the temp file goes
+        let blocked = dir.join("blocked.json");
+        fs::create_dir_all(blocked.join("inside")).unwrap();
+        assert!(write_config_file(&blocked, "{}", "connections file").is_err());
+        assert!(blocked.join("inside").is_dir());
+        assert_eq!(leftovers(), 0);
+
+        #[cfg(unix)]
+        {
+            let link = dir.join("linked.json");
+            std::os::unix::fs::symlink(&path, &link).unwrap();
+            write_config_file(&link, "{\"via\": \"link\"}", "connections file").unwrap();
+            assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+            assert_eq!(fs::read_to_string(&path).unwrap(), "{\"via\": \"link\"}");
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn interpolates_env_references_and_escapes() {
+        let lookup = |var: &str| (var == "DB_HOST").then(|| "db.internal".to_string());
+        assert_eq!(interpolate("host", "${DB_HOST}:x", lookup).unwrap(), "db.internal:x");
+        assert_eq!(interpolate("username", "a$$b$c", lookup).unwrap(), "a$b$c");
+        assert_eq!(interpolate("username", "$${DB_HOST}", lookup).unwrap(), "${DB_HOST}");
+
+        let err = interpolate("host", "${MISSING}", lookup).unwrap_err();
+        assert_eq!(crate::error::error_code(&err), "env_var_unset");
+        assert!(err.to_string().contains("${MISSING}"));
+        assert!(interpolate("host", "${DB_HOST", lookup).is_err());
+    }
+
+    #[test]
+    fn last_error_age_is_rounded_down_to_the_largest_unit() {
+        let ago = |secs: u64| LastError {
+            message: "FATAL:  SSL required".to_string(),
+            at: unix_now() - secs,
+        };
+        assert_eq!(ago(5).age(), "just now");
+        assert_eq!(ago(150).age(), "2m ago");
+        assert_eq!(ago(7200).age(), "2h ago");
+        assert_eq!(ago(3 * 86400 + 10).age(), "3d ago");
+    }
+
+    #[test]
+    fn durations_show_the_two_largest_units() {
+        assert_eq!(format_duration(42), "42s");
+        assert_eq!(format_duration(12 * 60 + 3), "12m3s");
+        assert_eq!(format_duration(2 * 3600 + 5 * 60 + 59), "2h5m");
+    }
+
+    #[test]
+    fn unwritable_config_errors_suggest_a_way_out() {
+        let path = Path::new("/etc/pg-vault/connections.json");
+        for kind in [io::ErrorKind::PermissionDenied, io::ErrorKind::ReadOnlyFilesystem] {
+            let err = config_io_error(io::Error::from(kind), "write connections file", path);
+            assert_eq!(error_code(&err), "config_not_writable");
+            assert!(err.to_string().contains("--config-dir"));
+        }
+
+        let err = config_io_error(io::Error::from(io::ErrorKind::StorageFull), "write connections file", path);
+        assert_ne!(error_code(&err), "config_not_writable");
+    }
+
+    #[test]
+    fn merge_conflict_markers_are_reported_by_line() {
+        let content = r#"{
+<<<<<<< HEAD
+  "app": {"host": "a", "port": 5432, "database": "app", "username": "app"}
+=======
+  "app": {"host": "b", "port": 5432, "database": "app", "username": "app"}
+>>>>>>> origin/main
+}"#;
+        let err = parse_connections(content).unwrap_err();
+        assert_eq!(error_code(&err), "merge_conflict");
+        assert!(err.to_string().starts_with("line 2 has a git merge conflict marker"));
+
+        // A value that merely contains the characters is fine
+        assert!(check_conflict_markers(r#"{"x": {"host": "<<<<<<<"}}"#).is_ok());
+    }
+
+    #[test]
+    fn most_recent_skips_removed_connections() {
+        let connections: HashMap<String, ConnectionInfo> = ["a", "b", "c"]
+            .into_iter()
+            .map(|name| (name.to_string(), info(name)))
+            .collect();
+        let last_used: HashMap<String, u64> =
+            [("a", 10), ("b", 30), ("c", 20), ("gone", 40)]
+                .into_iter()
+                .map(|(name, at)| (name.to_string(), at))
+                .collect();
+
+        let recent: Vec<&str> = most_recent(&last_used, &connections, 2)
+            .into_iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(recent, ["b", "c"]);
+    }
+
+    #[test]
+    fn idle_timeout_is_off_unless_positive() {
+        let tui = |json: &str| serde_json::from_str::<TuiSettings>(json).unwrap();
+
+        assert_eq!(tui("{}").idle_timeout(), None);
+        assert_eq!(tui(r#"{"idle_timeout_minutes": 0}"#).idle_timeout(), None);
+        assert_eq!(
+            tui(r#"{"idle_timeout_minutes": 15}"#).idle_timeout(),
+            Some(Duration::from_secs(900))
+        );
+    }
+
+    #[test]
+    fn later_drop_in_files_win_and_only_overrides_are_saved() {
+        let dir = std::env::temp_dir().join(format!("pg-vault-drop-ins-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("10-team.json"),
+            r#"{"shared": {"host": "a", "port": 5432, "database": "app", "username": "app"},
+                "reports": {"host": "r", "port": 5432, "database": "app", "username": "app"}}"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("20-override.json"),
+            r#"{"shared": {"host": "b", "port": 5432, "database": "app", "username": "app"}}"#,
+        )
+        .unwrap();
+        fs::write(dir.join("notes.txt"), "ignored").unwrap();
+
+        let drop_ins = load_drop_ins(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(drop_ins.len(), 2);
+        assert_eq!(drop_ins["shared"].info.host, "b");
+        assert!(drop_ins["shared"].path.ends_with("20-override.json"));
+
+        let mut connections: HashMap<String, ConnectionInfo> = drop_ins
+            .iter()
+            .map(|(name, drop_in)| (name.clone(), drop_in.info.clone()))
+            .collect();
+        connections.insert("mine".to_string(), info("m"));
+        connections.insert("reports".to_string(), info("local-reports"));
+
+        let saved: Vec<&String> = primary_entries(&connections, &drop_ins).into_keys().collect();
+        assert_eq!(saved, vec!["mine", "reports"]);
+    }
+}
@@ -1,29 +1,295 @@
 use anyhow::{Context, Result};
 use keyring::Entry;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config::{config_io_error, get_config_dir};
+use crate::error::coded;
+use crate::redact::Secret;
+
+/// Must be set to `1` before anything is written to the plaintext store.
+pub const PLAINTEXT_ENV: &str = "PG_VAULT_ALLOW_PLAINTEXT";
 
 pub fn store_password(name: &str, password: &str) -> Result<()> {
     let entry = Entry::new("pg-vault", name).context("Could not create keyring entry")?;
 
     entry
         .set_password(password)
-        .map_err(|e| anyhow::Error::from(e))
+        .map_err(anyhow::Error::from)
         .context("Could not store password in keyring")?;
 
+    // The plaintext store is read first, so an old entry there would shadow
+    // the password just saved
+    remove_plaintext_password(name)?;
     Ok(())
 }
 
-pub fn get_password(name: &str) -> Result<String> {
+/// Passwords kept unencrypted for throwaway CI environments without a
+/// keyring (`store --plaintext`). Only ever written with mode 0600.
+pub fn get_plaintext_path() -> Result<PathBuf> {
+    Ok(get_config_dir()?.join("plaintext-passwords.json"))
+}
+
+/// Refuse to touch the plaintext store unless `PG_VAULT_ALLOW_PLAINTEXT=1`,
+/// so it can't be turned on by a stray flag on a workstation.
+pub fn check_plaintext_allowed() -> Result<()> {
+    plaintext_allowed(std::env::var(PLAINTEXT_ENV).ok().as_deref())
+}
+
+fn plaintext_allowed(value: Option<&str>) -> Result<()> {
+    if value == Some("1") {
+        return Ok(());
+    }
+    Err(coded(
+        "plaintext_not_allowed",
+        format!(
+            "--plaintext writes the password unencrypted to disk and is only meant for \
+throwaway CI environments. Set {}=1 to allow it.",
+            PLAINTEXT_ENV
+        ),
+    ))
+}
+
+fn load_plaintext() -> Result<BTreeMap<String, String>> {
+    let path = get_plaintext_path()?;
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    let content = fs::read_to_string(path).context("Could not read plaintext password file")?;
+    serde_json::from_str(&content).context("Could not parse plaintext password file")
+}
+
+fn save_plaintext(passwords: &BTreeMap<String, String>) -> Result<()> {
+    let path = get_plaintext_path()?;
+    if passwords.is_empty() {
+        if path.exists() {
+            fs::remove_file(&path).context("Could not remove plaintext password file")?;
+        }
+        return Ok(());
+    }
+
+    let content = serde_json::to_string_pretty(passwords)
+        .context("Could not serialize plaintext passwords")?;
+    write_private(&path, &content)
+        .map_err(|e| config_io_error(e, "write the plaintext password file", &path))
+}
+
+/// Write `content` to `path` readable by the owner only.
+#[cfg(unix)]
+pub fn write_private(path: &std::path::Path, content: &str) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    // `mode` only applies when the file is created
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(content.as_bytes())
+}
+
+#[cfg(not(unix))]
+pub fn write_private(path: &std::path::Path, content: &str) -> std::io::Result<()> {
+    fs::write(path, content)
+}
+
+/// Save a password unencrypted, bypassing the keyring. Callers must have
+/// passed `check_plaintext_allowed` first.
+pub fn store_plaintext_password(name: &str, password: &str) -> Result<()> {
+    check_plaintext_allowed()?;
+    let mut passwords = load_plaintext()?;
+    passwords.insert(name.to_string(), password.to_string());
+    save_plaintext(&passwords)?;
+
+    // Don't leave a stale keyring copy to be picked up if this one is removed
+    if let Ok(entry) = Entry::new("pg-vault", name) {
+        let _ = entry.delete_credential();
+    }
+    Ok(())
+}
+
+fn remove_plaintext_password(name: &str) -> Result<bool> {
+    let mut passwords = load_plaintext()?;
+    if passwords.remove(name).is_none() {
+        return Ok(false);
+    }
+    save_plaintext(&passwords)?;
+    Ok(true)
+}
+
+/// Read a just-stored password back. Some keyring backends report success
+/// from `set_password` without persisting anything, so don't trust the write.
+pub fn verify_password(name: &str, expected: &str) -> Result<()> {
+    check_read_back(name, expected, get_password(name))
+}
+
+fn check_read_back(name: &str, expected: &str, stored: Result<Secret>) -> Result<()> {
+    let hint = "Your system keyring may not be saving secrets; on Linux, check that a \
+Secret Service provider (such as gnome-keyring or KeePassXC) is running and unlocked.";
+    match stored {
+        Ok(stored) if stored.expose() == expected => Ok(()),
+        Ok(_) => Err(coded(
+            "keyring_unverified",
+            format!(
+                "The keyring returned a different password for '{}' than the one just stored. {}",
+                name, hint
+            ),
+        )),
+        Err(e) => Err(coded(
+            "keyring_unverified",
+            format!(
+                "The password for '{}' was stored but could not be read back ({:#}). {}",
+                name, e, hint
+            ),
+        )),
+    }
+}
+
+pub fn get_password(name: &str) -> Result<Secret> {
+    if let Some(password) = load_plaintext()?.remove(name) {
+        return Ok(Secret::new(password));
+    }
     let entry = Entry::new("pg-vault", name).context("Could not create keyring entry")?;
     let password = entry
         .get_password()
         .context("Could not retrieve password from keyring")?;
-    Ok(password)
+    Ok(Secret::new(password))
+}
+
+/// Check whether a password is stored without needing its value. Only a
+/// definite "no entry" is `false`; other keyring failures are errors.
+pub fn has_password(name: &str) -> Result<bool> {
+    if load_plaintext()?.contains_key(name) {
+        return Ok(true);
+    }
+    let entry = Entry::new("pg-vault", name).context("Could not create keyring entry")?;
+    match entry.get_password() {
+        Ok(_) => Ok(true),
+        Err(keyring::Error::NoEntry) => Ok(false),
+        Err(e) => Err(anyhow::Error::from(e).context("Could not read keyring")),
+    }
 }
 
 pub fn remove_password(name: &str) -> Result<()> {
+    if remove_plaintext_password(name)? {
+        return Ok(());
+    }
     let entry = Entry::new("pg-vault", name).context("Could not create keyring entry")?;
     entry
         .delete_credential()
         .context("Could not remove password from keyring")?;
     Ok(())
 }
+
+/// Where passwords are kept, so commands can be tested against
+/// `memory::MemoryCredentials` instead of the system keyring.
+pub trait CredentialStore {
+    fn store_password(&self, name: &str, password: &str) -> Result<()>;
+
+    /// Callers must have passed `check_plaintext_allowed` first.
+    fn store_plaintext_password(&self, name: &str, password: &str) -> Result<()>;
+
+    fn get_password(&self, name: &str) -> Result<Secret>;
+
+    fn remove_password(&self, name: &str) -> Result<()>;
+
+    /// Read a just-stored password back, as `verify_password` does.
+    fn verify_password(&self, name: &str, expected: &str) -> Result<()> {
+        check_read_back(name, expected, self.get_password(name))
+    }
+}
+
+/// The system keyring, plus the plaintext store when one is in use.
+pub struct KeyringCredentials;
+
+impl CredentialStore for KeyringCredentials {
+    fn store_password(&self, name: &str, password: &str) -> Result<()> {
+        store_password(name, password)
+    }
+
+    fn store_plaintext_password(&self, name: &str, password: &str) -> Result<()> {
+        store_plaintext_password(name, password)
+    }
+
+    fn get_password(&self, name: &str) -> Result<Secret> {
+        get_password(name)
+    }
+
+    fn remove_password(&self, name: &str) -> Result<()> {
+        remove_password(name)
+    }
+}
+
+#[cfg(test)]
+pub mod memory {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    /// Keeps passwords in memory; plaintext ones are kept alongside.
+    #[derive(Default)]
+    pub struct MemoryCredentials {
+        pub passwords: RefCell<HashMap<String, String>>,
+    }
+
+    impl MemoryCredentials {
+        pub fn with(passwords: &[(&str, &str)]) -> Self {
+            MemoryCredentials {
+                passwords: RefCell::new(
+                    passwords
+                        .iter()
+                        .map(|(name, password)| (name.to_string(), password.to_string()))
+                        .collect(),
+                ),
+            }
+        }
+    }
+
+    impl CredentialStore for MemoryCredentials {
+        fn store_password(&self, name: &str, password: &str) -> Result<()> {
+            self.passwords
+                .borrow_mut()
+                .insert(name.to_string(), password.to_string());
+            Ok(())
+        }
+
+        fn store_plaintext_password(&self, name: &str, password: &str) -> Result<()> {
+            self.store_password(name, password)
+        }
+
+        fn get_password(&self, name: &str) -> Result<Secret> {
+            self.passwords
+                .borrow()
+                .get(name)
+                .map(Secret::new)
+                .context("Could not retrieve password from keyring")
+        }
+
+        fn remove_password(&self, name: &str) -> Result<()> {
+            self.passwords
+                .borrow_mut()
+                .remove(name)
+                .map(drop)
+                .context("Could not remove password from keyring")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::error_code;
+
+    #[test]
+    fn plaintext_needs_an_explicit_opt_in() {
+        assert!(plaintext_allowed(Some("1")).is_ok());
+        for value in [None, Some(""), Some("0"), Some("true")] {
+            let err = plaintext_allowed(value).unwrap_err();
+            assert_eq!(error_code(&err), "plaintext_not_allowed");
+        }
+    }
+}
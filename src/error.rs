@@ -0,0 +1,67 @@
+use std::fmt;
+
+use crate::redact::redact;
+
+/// An error carrying a stable, machine-readable code for `--json-errors`.
+/// Attach one with `coded(...)`; anything without a code reports `"error"`.
+#[derive(Debug)]
+pub struct CodedError {
+    pub code: &'static str,
+    message: String,
+}
+
+impl fmt::Display for CodedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for CodedError {}
+
+pub fn coded(code: &'static str, message: impl Into<String>) -> anyhow::Error {
+    CodedError {
+        code,
+        message: message.into(),
+    }
+    .into()
+}
+
+pub fn error_code(err: &anyhow::Error) -> &'static str {
+    err.chain()
+        .find_map(|cause| cause.downcast_ref::<CodedError>())
+        .map_or("error", |coded| coded.code)
+}
+
+/// Render an error as `{"error": "...", "code": "..."}`.
+pub fn to_json(err: &anyhow::Error) -> String {
+    serde_json::json!({
+        "error": redact(&format!("{:#}", err)),
+        "code": error_code(err),
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn code_survives_added_context() {
+        let err = Err::<(), _>(coded("connection_not_found", "Connection 'x' not found"))
+            .context("while connecting")
+            .unwrap_err();
+
+        let json: serde_json::Value = serde_json::from_str(&to_json(&err)).unwrap();
+        assert_eq!(json["code"], "connection_not_found");
+        assert_eq!(
+            json["error"],
+            "while connecting: Connection 'x' not found"
+        );
+    }
+
+    #[test]
+    fn uncoded_errors_use_generic_code() {
+        assert_eq!(error_code(&anyhow::anyhow!("boom")), "error");
+    }
+}
@@ -0,0 +1,149 @@
+use anyhow::{Context, Result};
+use std::process::Command;
+use std::time::Duration;
+
+use crate::config::{format_duration, load_settings, record_session_time, ConnectionInfo};
+use crate::error::coded;
+use crate::runner::{CommandRunner, SystemRunner};
+
+/// Run the connection's `pre_connect` command, or the global one, before
+/// connecting to `name`. Does nothing when neither is set.
+pub fn pre_connect(runner: &dyn CommandRunner, name: &str, info: &ConnectionInfo) -> Result<()> {
+    let global = load_settings()?.pre_connect;
+    match info.pre_connect.as_deref().or(global.as_deref()) {
+        Some(hook) => run_pre_connect(runner, name, info, hook),
+        None => Ok(()),
+    }
+}
+
+/// Report how long the psql or session shell for `name` lasted, add it to
+/// the connection's total for `stats`, and run its `post_disconnect` hook.
+/// The session is already over, so anything going wrong is only a warning.
+pub fn after_session(name: &str, info: &ConnectionInfo, elapsed: Duration) {
+    println!("Session to {} lasted {}", name, format_duration(elapsed.as_secs()));
+    if let Err(e) = record_session_time(name, elapsed.as_secs()) {
+        eprintln!("Warning: could not record session time for '{}': {:#}", name, e);
+    }
+
+    let hook = match load_settings() {
+        Ok(settings) => info.post_disconnect.clone().or(settings.post_disconnect),
+        Err(e) => {
+            eprintln!("Warning: {:#}", e);
+            info.post_disconnect.clone()
+        }
+    };
+    if let Some(hook) = hook
+        && let Err(e) = run_post_disconnect(&SystemRunner, name, info, &hook, elapsed)
+    {
+        eprintln!("Warning: {:#}", e);
+    }
+}
+
+/// `sh -c hook` with the connection's details (never its password) in the
+/// environment.
+fn hook_command(name: &str, info: &ConnectionInfo, hook: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", hook])
+        .env("PG_VAULT_CONNECTION", name)
+        .env("PGHOST", &info.host)
+        .env("PGPORT", info.port.to_string())
+        .env("PGDATABASE", &info.database)
+        .env("PGUSER", &info.username);
+    cmd
+}
+
+/// Run the pre-connect `hook`. Its output is captured, and only shown if it
+/// fails, which stops the connect.
+fn run_pre_connect(runner: &dyn CommandRunner, name: &str, info: &ConnectionInfo, hook: &str) -> Result<()> {
+    eprintln!("Running pre-connect hook: {}", hook);
+    let mut cmd = hook_command(name, info, hook);
+    let output = runner
+        .output(&mut cmd)
+        .context(format!("Failed to run pre-connect hook '{}'", hook))?;
+    if output.status.success() {
+        return Ok(());
+    }
+
+    let mut message = format!(
+        "Pre-connect hook '{}' exited with code {:?}; not connecting to '{}'",
+        hook, output.status.code, name
+    );
+    for stream in [&output.stdout, &output.stderr] {
+        let text = String::from_utf8_lossy(stream);
+        if !text.trim().is_empty() {
+            message.push('\n');
+            message.push_str(text.trim_end());
+        }
+    }
+    Err(coded("pre_connect_failed", message))
+}
+
+/// Run the post-disconnect `hook`, which also gets the session's length in
+/// `PG_VAULT_SESSION_SECONDS`. Its output goes straight to the terminal.
+fn run_post_disconnect(
+    runner: &dyn CommandRunner,
+    name: &str,
+    info: &ConnectionInfo,
+    hook: &str,
+    elapsed: Duration,
+) -> Result<()> {
+    let mut cmd = hook_command(name, info, hook);
+    cmd.env("PG_VAULT_SESSION_SECONDS", elapsed.as_secs().to_string());
+    let status = runner
+        .status(&mut cmd)
+        .context(format!("Failed to run post-disconnect hook '{}'", hook))?;
+    if !status.success() {
+        anyhow::bail!("Post-disconnect hook '{}' exited with code {:?}", hook, status.code);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::error_code;
+    use crate::runner::mock::MockRunner;
+
+    fn info() -> ConnectionInfo {
+        ConnectionInfo {
+            host: "db.internal".to_string(),
+            port: 5432,
+            database: "app".to_string(),
+            username: "alice".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn hook_gets_the_connection_details() {
+        let runner = MockRunner::succeeding("vpn up\n");
+        run_pre_connect(&runner, "app", &info(), "vpn-refresh --quiet").unwrap();
+
+        let call = runner.last_call();
+        assert_eq!(call.program, "sh");
+        assert_eq!(call.args, ["-c", "vpn-refresh --quiet"]);
+        assert_eq!(call.env("PG_VAULT_CONNECTION"), Some("app"));
+        assert_eq!(call.env("PGHOST"), Some("db.internal"));
+        assert_eq!(call.env("PGPASSWORD"), None);
+    }
+
+    #[test]
+    fn failing_hook_stops_the_connect_with_its_output() {
+        let runner = MockRunner::failing(2, "vpn: not logged in\n");
+        let err = run_pre_connect(&runner, "app", &info(), "vpn-refresh").unwrap_err();
+        assert_eq!(error_code(&err), "pre_connect_failed");
+        assert!(err.to_string().ends_with("\nvpn: not logged in"));
+    }
+
+    #[test]
+    fn post_disconnect_hook_gets_the_session_length() {
+        let runner = MockRunner::succeeding("");
+        run_post_disconnect(&runner, "app", &info(), "audit-log", Duration::from_secs(723)).unwrap();
+        let call = runner.last_call();
+        assert_eq!(call.args, ["-c", "audit-log"]);
+        assert_eq!(call.env("PG_VAULT_SESSION_SECONDS"), Some("723"));
+
+        let runner = MockRunner::failing(1, "");
+        assert!(run_post_disconnect(&runner, "app", &info(), "audit-log", Duration::ZERO).is_err());
+    }
+}
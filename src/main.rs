@@ -1,8 +1,23 @@
 mod aws;
+mod cert;
 mod cli;
+mod clipboard;
 mod config;
 mod credentials;
+mod error;
+mod hook;
+mod multiplexer;
+mod pgpass;
+mod probe;
+mod psql;
+mod psqlrc;
+mod rds_ca;
+mod redact;
+mod runner;
+mod tidy;
+mod token_cache;
 mod tui;
+mod tunnel;
 
 use anyhow::Result;
 use clap::Parser;
@@ -13,15 +28,61 @@ use cli::{run_command, Commands};
 #[command(name = "pg-vault")]
 #[command(about = "A CLI tool for managing PostgreSQL credentials")]
 struct Cli {
+    #[arg(long, global = true, help = "Print errors as JSON objects on stderr")]
+    json_errors: bool,
+    #[arg(
+        long,
+        global = true,
+        value_name = "DIR",
+        help = "Directory for connections, settings and other state [default: ~/.config/pg-vault, or $PG_VAULT_CONFIG_DIR]"
+    )]
+    config_dir: Option<std::path::PathBuf>,
+    #[arg(long, global = true, help = "Sign a new IAM token instead of reusing one from the last 15 minutes")]
+    no_cache: bool,
+    #[arg(
+        long,
+        visible_alias = "no-alt-screen",
+        help = "Run the TUI inline below the prompt instead of in the alternate screen"
+    )]
+    inline: bool,
+    #[arg(
+        long,
+        value_name = "COUNT",
+        num_args = 0..=1,
+        default_missing_value = "5",
+        help = "Open the TUI with a quick pick of the most recently used connections [default: 5]"
+    )]
+    recent: Option<usize>,
     #[command(subcommand)]
     command: Option<Commands>,
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
+    let json_errors = cli.json_errors;
+    if let Some(dir) = cli.config_dir {
+        config::set_config_dir(dir);
+    }
+    if cli.no_cache {
+        token_cache::disable();
+    }
 
-    match cli.command {
+    let result = match cli.command {
         Some(command) => run_command(command),
-        None => tui::run(),
+        None => tui::run(tui::Options {
+            inline: cli.inline,
+            recent: cli.recent,
+        }),
+    };
+
+    if let Err(err) = &result {
+        if json_errors {
+            eprintln!("{}", error::to_json(err));
+        } else {
+            // What returning the error would print, minus any password
+            eprintln!("Error: {}", redact::redact(&format!("{:?}", err)));
+        }
+        std::process::exit(1);
     }
+    Ok(())
 }
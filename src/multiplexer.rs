@@ -0,0 +1,115 @@
+use anyhow::{Context, Result};
+use std::process::Command;
+
+use crate::psql::shell_quote;
+use crate::runner::CommandRunner;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Multiplexer {
+    Tmux,
+    Wezterm,
+}
+
+impl Multiplexer {
+    /// Detect the multiplexer pg-vault is running inside from its environment.
+    pub fn detect() -> Option<Multiplexer> {
+        if std::env::var_os("TMUX").is_some() {
+            Some(Multiplexer::Tmux)
+        } else if std::env::var_os("WEZTERM_PANE").is_some() {
+            Some(Multiplexer::Wezterm)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Multiplexer::Tmux => "tmux",
+            Multiplexer::Wezterm => "WezTerm",
+        }
+    }
+
+    /// Build the command that opens a new pane running `program args...`.
+    pub fn split_command(&self, program: &str, args: &[&str]) -> Command {
+        match self {
+            Multiplexer::Tmux => {
+                // tmux runs the pane command through a shell, so quote each word
+                let shell_command = std::iter::once(program)
+                    .chain(args.iter().copied())
+                    .map(shell_quote)
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                let mut cmd = Command::new("tmux");
+                cmd.args(["split-window", "-h", &shell_command]);
+                cmd
+            }
+            Multiplexer::Wezterm => {
+                let mut cmd = Command::new("wezterm");
+                cmd.args(["cli", "split-pane", "--right", "--", program])
+                    .args(args);
+                cmd
+            }
+        }
+    }
+
+    pub fn open_pane(&self, runner: &dyn CommandRunner, program: &str, args: &[&str]) -> Result<()> {
+        let output = runner
+            .output(&mut self.split_command(program, args))
+            .context(format!("Failed to run {}", self.name()))?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "{} could not open a pane: {}",
+                self.name(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        if *self == Multiplexer::Tmux {
+            // Re-balance so several splits stay usable
+            let mut layout = Command::new("tmux");
+            layout.args(["select-layout", "tiled"]);
+            let _ = runner.output(&mut layout);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::mock::MockRunner;
+
+    #[test]
+    fn tmux_pane_runs_quoted_command_and_retiles() {
+        let runner = MockRunner::succeeding("");
+        Multiplexer::Tmux
+            .open_pane(&runner, "/usr/local/bin/pg-vault", &["connect", "my db"])
+            .unwrap();
+
+        let calls = runner.calls.borrow();
+        assert_eq!(
+            calls[0].args,
+            vec![
+                "split-window",
+                "-h",
+                "'/usr/local/bin/pg-vault' 'connect' 'my db'"
+            ]
+        );
+        assert_eq!(calls[1].args, vec!["select-layout", "tiled"]);
+    }
+
+    #[test]
+    fn wezterm_pane_passes_args_directly() {
+        let runner = MockRunner::succeeding("");
+        Multiplexer::Wezterm
+            .open_pane(&runner, "pg-vault", &["iam", "prod"])
+            .unwrap();
+
+        let call = runner.last_call();
+        assert_eq!(call.program, "wezterm");
+        assert_eq!(
+            call.args,
+            vec!["cli", "split-pane", "--right", "--", "pg-vault", "iam", "prod"]
+        );
+    }
+}
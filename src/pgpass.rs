@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config::ConnectionInfo;
+use crate::credentials::write_private;
+
+/// One `host:port:database:username:password` line from a `.pgpass` file.
+#[derive(Debug, PartialEq)]
+pub struct PgpassEntry {
+    pub host: String,
+    pub port: u16,
+    pub database: String,
+    pub username: String,
+    pub password: String,
+}
+
+/// The password file libpq reads: `$PGPASSFILE`, else `~/.pgpass`.
+pub fn pgpass_path() -> Option<PathBuf> {
+    match std::env::var_os("PGPASSFILE") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => Some(dirs::home_dir()?.join(".pgpass")),
+    }
+}
+
+/// Replace the password file at `path` with `content`, mode 0600. Written
+/// to a temporary file renamed over it, so an interrupted run never leaves
+/// the user's other entries truncated. A symlinked file is written through.
+pub fn write_pgpass(path: &Path, content: &str) -> io::Result<()> {
+    let target = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut temp_name = target.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(format!(".{}.tmp", std::process::id()));
+    let temp = target.with_file_name(temp_name);
+
+    let written = write_private(&temp, content)
+        .and_then(|()| fs::File::open(&temp)?.sync_all())
+        .and_then(|()| fs::rename(&temp, &target));
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written
+}
+
+/// Split a line on unescaped `:`, undoing `\:` and `\\` escapes.
+fn split_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(next) = chars.next() {
+                    fields.last_mut().unwrap().push(next);
+                }
+            }
+            ':' => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+/// Parse the entries of a `.pgpass` file that describe one concrete
+/// connection. Comments, malformed lines and wildcard (`*`) entries are
+/// skipped since they can't be turned into a stored connection.
+pub fn parse_pgpass(content: &str) -> Vec<PgpassEntry> {
+    content
+        .lines()
+        .map(|line| line.trim_end_matches('\r'))
+        .filter(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .filter_map(|line| {
+            let fields = split_fields(line);
+            let [host, port, database, username, password] = fields.as_slice() else {
+                return None;
+            };
+            if [host, port, database, username].iter().any(|field| *field == "*") {
+                return None;
+            }
+            Some(PgpassEntry {
+                host: host.clone(),
+                port: port.parse().ok()?,
+                database: database.clone(),
+                username: username.clone(),
+                password: password.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Escape `:` and `\` so a field reads back as written.
+fn escape_field(field: &str) -> String {
+    field.replace('\\', "\\\\").replace(':', "\\:")
+}
+
+/// `content` of an existing `.pgpass` file with `entries` added. Lines for
+/// the same host, port, database and user are replaced; everything else
+/// (comments, wildcards, other servers) is kept as it was.
+pub fn merge_pgpass(content: &str, entries: &[PgpassEntry]) -> String {
+    let mut lines: Vec<String> = content
+        .lines()
+        .filter(|line| {
+            let fields = split_fields(line.trim_end_matches('\r'));
+            !entries.iter().any(|entry| entry.is_for(&fields))
+        })
+        .map(str::to_string)
+        .collect();
+    lines.extend(entries.iter().map(PgpassEntry::to_line));
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+impl PgpassEntry {
+    pub fn from_connection(info: &ConnectionInfo, password: &str) -> Self {
+        PgpassEntry {
+            host: info.host.clone(),
+            port: info.port,
+            database: info.database.clone(),
+            username: info.username.clone(),
+            password: password.to_string(),
+        }
+    }
+
+    /// Whether a line's `fields` are for the same server and role.
+    fn is_for(&self, fields: &[String]) -> bool {
+        matches!(fields, [host, port, database, username, _]
+            if *host == self.host
+                && *port == self.port.to_string()
+                && *database == self.database
+                && *username == self.username)
+    }
+
+    pub fn to_line(&self) -> String {
+        [
+            escape_field(&self.host),
+            self.port.to_string(),
+            escape_field(&self.database),
+            escape_field(&self.username),
+            escape_field(&self.password),
+        ]
+        .join(":")
+    }
+
+    pub fn to_connection(&self) -> ConnectionInfo {
+        ConnectionInfo {
+            host: self.host.clone(),
+            port: self.port,
+            database: self.database.clone(),
+            username: self.username.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// A connection name that isn't taken yet: the database name, then
+    /// `database-host`, then numbered.
+    pub fn suggest_name(&self, existing: &HashMap<String, ConnectionInfo>) -> String {
+        let host_label = self.host.split('.').next().unwrap_or(&self.host);
+        let candidates = [
+            self.database.clone(),
+            format!("{}-{}", self.database, host_label),
+        ];
+        if let Some(name) = candidates.into_iter().find(|name| !existing.contains_key(name)) {
+            return name;
+        }
+        (2..)
+            .map(|n| format!("{}-{}-{}", self.database, host_label, n))
+            .find(|name| !existing.contains_key(name))
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_escaped_fields_and_skips_wildcards() {
+        let content = "\
+# staging
+db.example.com:5432:app:alice:pa\\:ss\\\\word
+*:*:*:postgres:secret
+localhost:notaport:app:bob:x
+short:line
+";
+        assert_eq!(
+            parse_pgpass(content),
+            vec![PgpassEntry {
+                host: "db.example.com".to_string(),
+                port: 5432,
+                database: "app".to_string(),
+                username: "alice".to_string(),
+                password: "pa:ss\\word".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn written_lines_escape_colons_and_backslashes() {
+        let entry = PgpassEntry {
+            host: "db.example.com".to_string(),
+            port: 5432,
+            database: "app".to_string(),
+            username: "alice".to_string(),
+            password: "pa:ss\\word".to_string(),
+        };
+        assert_eq!(entry.to_line(), "db.example.com:5432:app:alice:pa\\:ss\\\\word");
+        assert_eq!(parse_pgpass(&entry.to_line()), vec![entry]);
+    }
+
+    #[test]
+    fn merging_replaces_only_the_same_server_and_role() {
+        let existing = "# mine\n*:*:*:postgres:secret\ndb.example.com:5432:app:alice:old\nother:5432:app:alice:keep\n";
+        let entry = parse_pgpass("db.example.com:5432:app:alice:new").remove(0);
+        assert_eq!(
+            merge_pgpass(existing, &[entry]),
+            "# mine\n*:*:*:postgres:secret\nother:5432:app:alice:keep\ndb.example.com:5432:app:alice:new\n"
+        );
+    }
+
+    #[test]
+    fn writing_replaces_the_file_without_leaving_a_temporary_one() {
+        let dir = std::env::temp_dir().join(format!("pg-vault-pgpass-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(".pgpass");
+        fs::write(&path, "*:*:*:postgres:secret\n").unwrap();
+
+        write_pgpass(&path, "*:*:*:postgres:secret\ndb:5432:app:alice:pw\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "*:*:*:postgres:secret\ndb:5432:app:alice:pw\n");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn suggested_names_avoid_existing_connections() {
+        let entry = parse_pgpass("db.example.com:5432:app:alice:pw").remove(0);
+        let mut existing = HashMap::new();
+        assert_eq!(entry.suggest_name(&existing), "app");
+
+        existing.insert("app".to_string(), ConnectionInfo::default());
+        assert_eq!(entry.suggest_name(&existing), "app-db");
+
+        existing.insert("app-db".to_string(), ConnectionInfo::default());
+        assert_eq!(entry.suggest_name(&existing), "app-db-2");
+    }
+}
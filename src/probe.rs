@@ -0,0 +1,177 @@
+use anyhow::Result;
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::error::coded;
+
+/// How long the pre-flight TCP probe waits for each address.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Why a connection attempt failed, worked out from what libpq printed or
+/// from the pre-flight probe.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConnectFailure {
+    Dns,
+    Refused,
+    Timeout,
+    Unreachable,
+    Tls,
+    Auth,
+}
+
+impl ConnectFailure {
+    /// Error code for `--json-errors`.
+    pub fn code(self) -> &'static str {
+        match self {
+            ConnectFailure::Dns => "dns_failed",
+            ConnectFailure::Refused => "connection_refused",
+            ConnectFailure::Timeout => "connection_timeout",
+            ConnectFailure::Unreachable => "network_unreachable",
+            ConnectFailure::Tls => "tls_failed",
+            ConnectFailure::Auth => "auth_failed",
+        }
+    }
+
+    pub fn describe(self) -> &'static str {
+        match self {
+            ConnectFailure::Dns => "DNS lookup failed",
+            ConnectFailure::Refused => "Connection refused (nothing listening on that port?)",
+            ConnectFailure::Timeout => "Connection timed out (firewall, security group or VPN?)",
+            ConnectFailure::Unreachable => "Network unreachable",
+            ConnectFailure::Tls => "TLS handshake failed",
+            ConnectFailure::Auth => "Authentication failed",
+        }
+    }
+
+    /// Match libpq's error text. Authentication is checked first since a
+    /// pg_hba.conf rejection also mentions SSL.
+    pub fn classify(stderr: &str) -> Option<Self> {
+        let text = stderr.to_lowercase();
+        let has = |patterns: &[&str]| patterns.iter().any(|pattern| text.contains(pattern));
+
+        if has(&[
+            "authentication failed",
+            "no pg_hba.conf entry",
+            "no password supplied",
+            "channel binding",
+            "scram",
+        ]) || (text.contains("role \"") && text.contains("does not exist"))
+        {
+            Some(ConnectFailure::Auth)
+        } else if has(&[
+            "could not translate host name",
+            "name or service not known",
+            "nodename nor servname",
+            "temporary failure in name resolution",
+            "no address associated with hostname",
+        ]) {
+            Some(ConnectFailure::Dns)
+        } else if has(&["connection refused"]) {
+            Some(ConnectFailure::Refused)
+        } else if has(&["timeout expired", "timed out"]) {
+            Some(ConnectFailure::Timeout)
+        } else if has(&["no route to host", "network is unreachable"]) {
+            Some(ConnectFailure::Unreachable)
+        } else if has(&["ssl", "tls", "certificate"]) {
+            Some(ConnectFailure::Tls)
+        } else {
+            None
+        }
+    }
+
+    fn from_io(err: &io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::ConnectionRefused => ConnectFailure::Refused,
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => ConnectFailure::Timeout,
+            _ => ConnectFailure::Unreachable,
+        }
+    }
+}
+
+/// Check that `host:port` resolves and accepts TCP connections before psql
+/// tries, so a network problem is reported as one. Unix socket paths are
+/// left to psql.
+pub fn probe_tcp(host: &str, port: u16) -> Result<()> {
+    if host.starts_with('/') {
+        return Ok(());
+    }
+
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let addrs: Vec<_> = match (host, port).to_socket_addrs() {
+        Ok(addrs) => addrs.collect(),
+        Err(e) => return Err(failure(ConnectFailure::Dns, host, port, &e.to_string())),
+    };
+    if addrs.is_empty() {
+        return Err(failure(ConnectFailure::Dns, host, port, "no addresses found"));
+    }
+
+    let mut last_error = None;
+    for addr in &addrs {
+        match TcpStream::connect_timeout(addr, PROBE_TIMEOUT) {
+            Ok(_) => return Ok(()),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    let err = last_error.expect("at least one address was tried");
+    Err(failure(ConnectFailure::from_io(&err), host, port, &err.to_string()))
+}
+
+fn failure(kind: ConnectFailure, host: &str, port: u16, detail: &str) -> anyhow::Error {
+    coded(
+        kind.code(),
+        format!("{} for {}:{}: {}", kind.describe(), host, port, detail),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::error_code;
+    use std::net::TcpListener;
+
+    #[test]
+    fn libpq_errors_are_classified() {
+        let cases = [
+            (
+                "psql: error: could not translate host name \"nope.invalid\" to address: Name or service not known",
+                Some(ConnectFailure::Dns),
+            ),
+            (
+                "psql: error: connection to server at \"db\" (10.0.0.5), port 5432 failed: Connection refused",
+                Some(ConnectFailure::Refused),
+            ),
+            (
+                "psql: error: connection to server at \"db\" (10.0.0.5), port 5432 failed: timeout expired",
+                Some(ConnectFailure::Timeout),
+            ),
+            (
+                "psql: error: connection to server at \"db\" (10.0.0.5), port 5432 failed: SSL error: certificate verify failed",
+                Some(ConnectFailure::Tls),
+            ),
+            (
+                "psql: error: connection to server at \"db\" (10.0.0.5), port 5432 failed: FATAL:  password authentication failed for user \"app\"",
+                Some(ConnectFailure::Auth),
+            ),
+            (
+                "FATAL:  no pg_hba.conf entry for host \"10.0.0.9\", user \"app\", database \"app\", SSL off",
+                Some(ConnectFailure::Auth),
+            ),
+            ("FATAL:  database \"gone\" does not exist", None),
+        ];
+        for (stderr, expected) in cases {
+            assert_eq!(ConnectFailure::classify(stderr), expected, "{}", stderr);
+        }
+    }
+
+    #[test]
+    fn probe_reports_refused_ports() {
+        // Bind then drop, so the port is known to be closed
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let err = probe_tcp("127.0.0.1", port).unwrap_err();
+        assert_eq!(error_code(&err), "connection_refused");
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        probe_tcp("127.0.0.1", listener.local_addr().unwrap().port()).unwrap();
+    }
+}
@@ -0,0 +1,143 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::ConnectionInfo;
+
+/// A psqlrc generated for a single psql session. psql reads it via the
+/// `PSQLRC` environment variable; the file is removed when this is dropped.
+pub struct SessionPsqlrc {
+    path: PathBuf,
+}
+
+impl SessionPsqlrc {
+    pub fn write(contents: &str) -> Result<Self> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0);
+        let path = std::env::temp_dir().join(format!(
+            "pg-vault-psqlrc-{}-{}",
+            std::process::id(),
+            nanos
+        ));
+
+        fs::write(&path, contents)
+            .with_context(|| format!("Failed to write psqlrc to {}", path.display()))?;
+        Ok(Self { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for SessionPsqlrc {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// The psqlrc psql would load on its own: `$PSQLRC`, else `~/.psqlrc`.
+pub fn user_psqlrc() -> Option<PathBuf> {
+    let path = match std::env::var_os("PSQLRC") {
+        Some(path) => PathBuf::from(path),
+        None => dirs::home_dir()?.join(".psqlrc"),
+    };
+    path.is_file().then_some(path)
+}
+
+/// Quote a value as a single-quoted psql meta-command argument.
+fn psql_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+/// Generate the psqlrc for a connection. The user's own psqlrc is sourced
+/// first so their settings still apply; ours are layered on top. The
+/// prompt carries the connection name so it's obvious which one a
+/// terminal is attached to.
+pub fn render(name: &str, info: &ConnectionInfo, user_rc: Option<&Path>) -> String {
+    let mut lines = Vec::new();
+    if let Some(user_rc) = user_rc {
+        lines.push(format!("\\i {}", psql_quote(&user_rc.display().to_string())));
+    }
+
+    // `%` introduces prompt escapes, so a literal one must be doubled
+    let label = name.replace('%', "%%");
+    let prompt = if info.protected {
+        format!("%[%033[1;31m%][{} PROTECTED] %/%R%#%[%033[0m%] ", label)
+    } else {
+        format!("[{}] %/%R%# ", label)
+    };
+    lines.push(format!("\\set PROMPT1 {}", psql_quote(&prompt)));
+    lines.push(format!("\\set PROMPT2 {}", psql_quote(&prompt)));
+
+    if info.protected {
+        // Nothing is committed until the user types COMMIT, so a stray DROP
+        // or TRUNCATE can still be rolled back
+        lines.push("\\set ON_ERROR_STOP on".to_string());
+        lines.push("\\set AUTOCOMMIT off".to_string());
+        lines.push(
+            "\\echo 'Protected connection: autocommit is off. Run COMMIT to apply changes or ROLLBACK to undo them.'"
+                .to_string(),
+        );
+    }
+
+    lines.join("\n") + "\n"
+}
+
+/// Generate a psqlrc that only sets `variables`, for psql sessions started
+/// from a `session` shell. The user's own psqlrc still runs first.
+pub fn render_variables(variables: &[(String, String)], user_rc: Option<&Path>) -> String {
+    let mut lines = Vec::new();
+    if let Some(user_rc) = user_rc {
+        lines.push(format!("\\i {}", psql_quote(&user_rc.display().to_string())));
+    }
+    for (name, value) in variables {
+        lines.push(format!("\\set {} {}", name, psql_quote(value)));
+    }
+    lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prompt_shows_connection_name() {
+        let rc = render("prod", &ConnectionInfo::default(), None);
+        assert_eq!(
+            rc,
+            "\\set PROMPT1 '[prod] %/%R%# '\n\\set PROMPT2 '[prod] %/%R%# '\n"
+        );
+    }
+
+    #[test]
+    fn prompt_escapes_percent_and_quotes_in_names() {
+        let rc = render("50%'s", &ConnectionInfo::default(), None);
+        assert!(rc.starts_with("\\set PROMPT1 '[50%%\\'s] %/%R%# '"));
+    }
+
+    #[test]
+    fn session_variables_are_set_after_the_user_rc() {
+        let variables = vec![("search_path".to_string(), "app's".to_string())];
+        let rc = render_variables(&variables, Some(Path::new("/home/me/.psqlrc")));
+        assert_eq!(rc, "\\i '/home/me/.psqlrc'\n\\set search_path 'app\\'s'\n");
+    }
+
+    #[test]
+    fn protected_connections_source_user_rc_then_disable_autocommit() {
+        let info = ConnectionInfo {
+            protected: true,
+            ..Default::default()
+        };
+        let rc = render("prod", &info, Some(Path::new("/home/me/.psqlrc")));
+        let lines: Vec<&str> = rc.lines().collect();
+
+        assert_eq!(lines[0], "\\i '/home/me/.psqlrc'");
+        assert!(lines.contains(&"\\set AUTOCOMMIT off"));
+        assert!(lines.contains(&"\\set ON_ERROR_STOP on"));
+        assert!(rc.contains("[prod PROTECTED]"));
+    }
+}
@@ -0,0 +1,112 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::config::{get_config_dir, ConnectionInfo};
+use crate::psql::effective_sslmode;
+use crate::runner::CommandRunner;
+
+/// Amazon's bundle of every RDS region's root and intermediate CAs.
+const RDS_CA_BUNDLE_URL: &str = "https://truststore.pki.rds.amazonaws.com/global/global-bundle.pem";
+const RDS_CA_BUNDLE_FILE: &str = "rds-global-bundle.pem";
+
+fn is_rds_host(host: &str) -> bool {
+    host.ends_with(".rds.amazonaws.com") || host.ends_with(".rds.amazonaws.com.cn")
+}
+
+/// Whether libpq will need the RDS CA bundle to verify this connection's
+/// server certificate.
+pub fn needs_rds_ca(info: &ConnectionInfo) -> bool {
+    is_rds_host(&info.host)
+        && matches!(effective_sslmode(info).as_str(), "verify-ca" | "verify-full")
+}
+
+/// The `PGSSLROOTCERT` to use for a connection, downloading the RDS bundle
+/// the first time it's needed. A root cert stored with the connection or
+/// already set in the environment is left alone.
+pub fn root_cert_for(runner: &dyn CommandRunner, info: &ConnectionInfo) -> Result<Option<PathBuf>> {
+    if !needs_rds_ca(info) || info.sslrootcert.is_some() || std::env::var_os("PGSSLROOTCERT").is_some() {
+        return Ok(None);
+    }
+    ensure_bundle_in(runner, &get_config_dir()?).map(Some)
+}
+
+/// Return the cached bundle in `dir`, downloading it with curl if missing.
+fn ensure_bundle_in(runner: &dyn CommandRunner, dir: &Path) -> Result<PathBuf> {
+    let path = dir.join(RDS_CA_BUNDLE_FILE);
+    if path.is_file() {
+        return Ok(path);
+    }
+
+    eprintln!("Downloading the Amazon RDS CA bundle to {}...", path.display());
+
+    // Download next to the destination and rename, so an interrupted
+    // download never leaves a truncated bundle in the cache
+    let partial = dir.join(format!("{}.download", RDS_CA_BUNDLE_FILE));
+    let mut cmd = Command::new("curl");
+    cmd.arg("-fsSL").arg("-o").arg(&partial).arg(RDS_CA_BUNDLE_URL);
+
+    let output = runner
+        .output(&mut cmd)
+        .context("Failed to run curl to download the RDS CA bundle. Make sure curl is installed.")?;
+    if !output.status.success() {
+        let _ = fs::remove_file(&partial);
+        anyhow::bail!(
+            "Downloading the RDS CA bundle from {} failed: {}",
+            RDS_CA_BUNDLE_URL,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    fs::rename(&partial, &path).context("Could not save the RDS CA bundle")?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::mock::MockRunner;
+
+    fn rds(sslmode: Option<&str>) -> ConnectionInfo {
+        ConnectionInfo {
+            host: "orders.abc123.us-east-1.rds.amazonaws.com".to_string(),
+            sslmode: sslmode.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn only_verifying_rds_connections_need_the_bundle() {
+        assert!(needs_rds_ca(&rds(Some("verify-full"))));
+        assert!(needs_rds_ca(&rds(Some("verify-ca"))));
+        assert!(!needs_rds_ca(&rds(Some("require"))));
+
+        let other = ConnectionInfo {
+            host: "db.example.com".to_string(),
+            ..rds(Some("verify-full"))
+        };
+        assert!(!needs_rds_ca(&other));
+    }
+
+    #[test]
+    fn cached_bundle_is_reused_and_missing_one_is_fetched_with_curl() {
+        let dir = std::env::temp_dir().join(format!("pg-vault-rds-ca-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        // The mock doesn't write the file, so the download can't complete
+        let runner = MockRunner::succeeding("");
+        assert!(ensure_bundle_in(&runner, &dir).is_err());
+        let call = runner.last_call();
+        assert_eq!(call.program, "curl");
+        assert_eq!(call.args.last().map(String::as_str), Some(RDS_CA_BUNDLE_URL));
+
+        fs::write(dir.join(RDS_CA_BUNDLE_FILE), "-----BEGIN CERTIFICATE-----").unwrap();
+        let runner = MockRunner::succeeding("");
+        let path = ensure_bundle_in(&runner, &dir).unwrap();
+        assert_eq!(path, dir.join(RDS_CA_BUNDLE_FILE));
+        assert!(runner.calls.borrow().is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
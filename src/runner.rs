@@ -0,0 +1,245 @@
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use signal_hook::consts::SIGINT;
+use signal_hook::flag;
+use signal_hook::low_level;
+use signal_hook::SigId;
+
+/// How much of an interactive command's stderr is kept for error reports.
+const STDERR_TAIL: usize = 4096;
+
+/// Exit status of a command, independent of the platform `ExitStatus`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CommandStatus {
+    pub code: Option<i32>,
+}
+
+impl CommandStatus {
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CommandOutput {
+    pub status: CommandStatus,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+/// Executes external commands (psql, aws, the user's shell). Code builds the
+/// `Command` and hands it to a runner, so tests can inspect what would run.
+pub trait CommandRunner {
+    /// Run to completion, capturing stdout and stderr.
+    fn output(&self, cmd: &mut Command) -> io::Result<CommandOutput>;
+
+    /// Run with whatever stdio the command was configured with.
+    fn status(&self, cmd: &mut Command) -> io::Result<CommandStatus>;
+
+    /// Run interactively, echoing stderr to ours as it arrives while keeping
+    /// its tail, so a failure can be explained after the command exits.
+    fn status_capturing_stderr(&self, cmd: &mut Command) -> io::Result<(CommandStatus, Vec<u8>)>;
+}
+
+/// Runs commands for real.
+pub struct SystemRunner;
+
+/// How many `SigintIgnored` guards are alive, so tests can check none leak.
+static SIGINT_GUARDS: AtomicUsize = AtomicUsize::new(0);
+
+/// Ignores Ctrl+C in pg-vault until dropped, leaving it to a child process
+/// (e.g. psql cancelling a query). Dropping it restores the previous
+/// handling, so later prompts can still be interrupted.
+struct SigintIgnored(Option<SigId>);
+
+impl SigintIgnored {
+    fn register() -> Self {
+        let id = flag::register(SIGINT, Arc::new(AtomicBool::new(false))).ok();
+        if id.is_some() {
+            SIGINT_GUARDS.fetch_add(1, Ordering::SeqCst);
+        }
+        SigintIgnored(id)
+    }
+}
+
+impl Drop for SigintIgnored {
+    fn drop(&mut self) {
+        if let Some(id) = self.0.take() {
+            low_level::unregister(id);
+            SIGINT_GUARDS.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Copy `stderr` to ours, keeping its last `STDERR_TAIL` bytes in `captured`.
+fn tee_stderr(stderr: &mut impl Read, captured: &mut Vec<u8>) -> io::Result<()> {
+    let mut buf = [0u8; 4096];
+    loop {
+        let read = stderr.read(&mut buf)?;
+        if read == 0 {
+            return Ok(());
+        }
+        io::stderr().write_all(&buf[..read])?;
+        captured.extend_from_slice(&buf[..read]);
+        if captured.len() > STDERR_TAIL {
+            captured.drain(..captured.len() - STDERR_TAIL);
+        }
+    }
+}
+
+impl CommandRunner for SystemRunner {
+    fn output(&self, cmd: &mut Command) -> io::Result<CommandOutput> {
+        let output = cmd.output()?;
+        Ok(CommandOutput {
+            status: CommandStatus {
+                code: output.status.code(),
+            },
+            stdout: output.stdout,
+            stderr: output.stderr,
+        })
+    }
+
+    fn status(&self, cmd: &mut Command) -> io::Result<CommandStatus> {
+        let status = cmd.status()?;
+        Ok(CommandStatus {
+            code: status.code(),
+        })
+    }
+
+    fn status_capturing_stderr(&self, cmd: &mut Command) -> io::Result<(CommandStatus, Vec<u8>)> {
+        // Ctrl+C is meant for the child (e.g. cancelling a query); dying on it
+        // would close the pipe under the child. Declared first so it's
+        // dropped only after the child has been reaped.
+        let _sigint = SigintIgnored::register();
+
+        let mut child = cmd.stderr(Stdio::piped()).spawn()?;
+        let mut stderr = child.stderr.take().expect("stderr is piped");
+        let mut captured = Vec::new();
+        if let Err(e) = tee_stderr(&mut stderr, &mut captured) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(e);
+        }
+
+        let status = child.wait()?;
+        Ok((
+            CommandStatus {
+                code: status.code(),
+            },
+            captured,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interactive_commands_keep_stderr_and_restore_ctrl_c() {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "echo 'FATAL: password authentication failed' >&2; exit 2"]);
+
+        let (status, stderr) = SystemRunner.status_capturing_stderr(&mut cmd).unwrap();
+
+        assert_eq!(status.code, Some(2));
+        assert_eq!(stderr, b"FATAL: password authentication failed\n");
+        assert_eq!(SIGINT_GUARDS.load(Ordering::SeqCst), 0);
+    }
+}
+
+#[cfg(test)]
+pub mod mock {
+    use super::*;
+    use std::cell::RefCell;
+
+    #[derive(Debug, Clone)]
+    pub struct RecordedCommand {
+        pub program: String,
+        pub args: Vec<String>,
+        pub envs: Vec<(String, String)>,
+    }
+
+    impl RecordedCommand {
+        pub fn env(&self, key: &str) -> Option<&str> {
+            self.envs
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.as_str())
+        }
+    }
+
+    /// Records every command instead of running it and replies with a canned output.
+    pub struct MockRunner {
+        pub calls: RefCell<Vec<RecordedCommand>>,
+        pub response: CommandOutput,
+    }
+
+    impl MockRunner {
+        pub fn succeeding(stdout: &str) -> Self {
+            Self::with_response(0, stdout, "")
+        }
+
+        pub fn failing(code: i32, stderr: &str) -> Self {
+            Self::with_response(code, "", stderr)
+        }
+
+        fn with_response(code: i32, stdout: &str, stderr: &str) -> Self {
+            MockRunner {
+                calls: RefCell::new(Vec::new()),
+                response: CommandOutput {
+                    status: CommandStatus { code: Some(code) },
+                    stdout: stdout.as_bytes().to_vec(),
+                    stderr: stderr.as_bytes().to_vec(),
+                },
+            }
+        }
+
+        pub fn last_call(&self) -> RecordedCommand {
+            self.calls
+                .borrow()
+                .last()
+                .cloned()
+                .expect("no command was run")
+        }
+
+        fn record(&self, cmd: &Command) {
+            self.calls.borrow_mut().push(RecordedCommand {
+                program: cmd.get_program().to_string_lossy().into_owned(),
+                args: cmd
+                    .get_args()
+                    .map(|arg| arg.to_string_lossy().into_owned())
+                    .collect(),
+                envs: cmd
+                    .get_envs()
+                    .filter_map(|(k, v)| {
+                        Some((
+                            k.to_string_lossy().into_owned(),
+                            v?.to_string_lossy().into_owned(),
+                        ))
+                    })
+                    .collect(),
+            });
+        }
+    }
+
+    impl CommandRunner for MockRunner {
+        fn output(&self, cmd: &mut Command) -> io::Result<CommandOutput> {
+            self.record(cmd);
+            Ok(self.response.clone())
+        }
+
+        fn status(&self, cmd: &mut Command) -> io::Result<CommandStatus> {
+            self.record(cmd);
+            Ok(self.response.status)
+        }
+
+        fn status_capturing_stderr(&self, cmd: &mut Command) -> io::Result<(CommandStatus, Vec<u8>)> {
+            self.record(cmd);
+            Ok((self.response.status, self.response.stderr.clone()))
+        }
+    }
+}
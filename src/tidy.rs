@@ -0,0 +1,116 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::config::ConnectionInfo;
+
+/// Groups of connection names that point at the same host, port, database
+/// and user. Each group is sorted by name, and groups are ordered by their
+/// first name.
+pub fn find_duplicate_groups(connections: &HashMap<String, ConnectionInfo>) -> Vec<Vec<String>> {
+    let mut by_target: BTreeMap<(String, u16, &str, &str), Vec<String>> = BTreeMap::new();
+    for (name, info) in connections {
+        by_target
+            .entry((
+                info.host.to_lowercase(),
+                info.port,
+                info.database.as_str(),
+                info.username.as_str(),
+            ))
+            .or_default()
+            .push(name.clone());
+    }
+
+    let mut groups: Vec<Vec<String>> = by_target
+        .into_values()
+        .filter(|names| names.len() > 1)
+        .map(|mut names| {
+            names.sort();
+            names
+        })
+        .collect();
+    groups.sort();
+    groups
+}
+
+/// Fold `other` into `keep`: tags are unioned, optional settings that
+/// `keep` lacks are taken from `other`, and protection is never dropped.
+pub fn merge_into(keep: &mut ConnectionInfo, other: &ConnectionInfo) {
+    for tag in &other.tags {
+        if !keep.tags.contains(tag) {
+            keep.tags.push(tag.clone());
+        }
+    }
+    if keep.client_version.is_none() {
+        keep.client_version = other.client_version;
+    }
+    // A connection has at most one way of tunneling, Cloud SQL included
+    if !keep.uses_tunnel() {
+        keep.bastion = other.bastion.clone();
+        keep.ssh_jump = other.ssh_jump.clone();
+    }
+    if keep.profiles.is_empty() {
+        keep.profiles = other.profiles.clone();
+    }
+    // Only valid on IAM connections
+    if keep.iam_auth && keep.region.is_none() {
+        keep.region = other.region.clone();
+    }
+    if keep.sslmode.is_none() {
+        keep.sslmode = other.sslmode.clone();
+    }
+    keep.protected |= other.protected;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(host: &str, database: &str, tags: &[&str]) -> ConnectionInfo {
+        ConnectionInfo {
+            host: host.to_string(),
+            port: 5432,
+            database: database.to_string(),
+            username: "app".to_string(),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn groups_connections_with_the_same_target() {
+        let connections = HashMap::from([
+            ("prod".to_string(), info("db.example.com", "app", &[])),
+            ("prod-old".to_string(), info("DB.example.com", "app", &[])),
+            ("staging".to_string(), info("db.example.com", "staging", &[])),
+        ]);
+
+        assert_eq!(
+            find_duplicate_groups(&connections),
+            vec![vec!["prod".to_string(), "prod-old".to_string()]]
+        );
+    }
+
+    #[test]
+    fn merge_unions_tags_and_fills_missing_settings() {
+        let mut keep = info("h", "d", &["team"]);
+        let mut other = info("h", "d", &["team", "prod"]);
+        other.bastion = Some("vpc".to_string());
+
+        merge_into(&mut keep, &other);
+
+        assert_eq!(keep.tags, vec!["team", "prod"]);
+        assert_eq!(keep.bastion.as_deref(), Some("vpc"));
+    }
+
+    #[test]
+    fn merge_leaves_a_cloud_sql_connection_without_a_bastion() {
+        let mut keep = info("h", "d", &[]);
+        keep.cloud_sql_instance = Some("project:region:instance".to_string());
+        let mut other = info("h", "d", &[]);
+        other.ssh_jump = Some("bastion".to_string());
+
+        merge_into(&mut keep, &other);
+
+        assert_eq!(keep.ssh_jump, None);
+        assert_eq!(keep.bastion, None);
+    }
+}
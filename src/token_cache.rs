@@ -0,0 +1,130 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::config::{config_io_error, get_config_dir, unix_now};
+use crate::credentials::write_private;
+use crate::redact::Secret;
+
+/// RDS auth tokens are valid for 15 minutes from when they're signed.
+const TOKEN_LIFETIME: u64 = 15 * 60;
+
+/// A cached token with less than this left is signed again, so it can't
+/// expire between being handed out and psql logging in with it.
+const MIN_REMAINING: u64 = 60;
+
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// IAM tokens signed in the last 15 minutes, so connecting again soon after
+/// doesn't sign a new one. Only ever written with mode 0600.
+pub fn get_token_cache_path() -> Result<PathBuf> {
+    Ok(get_config_dir()?.join("iam-tokens.json"))
+}
+
+/// Neither reuse nor save tokens for the rest of this run (`--no-cache`).
+pub fn disable() {
+    DISABLED.store(true, Ordering::Relaxed);
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CachedToken {
+    token: String,
+    /// Seconds since the Unix epoch
+    expires_at: u64,
+}
+
+/// What a token is signed for. Tokens for the same role on the same
+/// endpoint signed with another profile or region aren't interchangeable.
+pub fn key(host: &str, port: u16, username: &str, profile: Option<&str>, region: Option<&str>) -> String {
+    format!(
+        "{}@{}:{} profile={} region={}",
+        username,
+        host,
+        port,
+        profile.unwrap_or("-"),
+        region.unwrap_or("-")
+    )
+}
+
+/// A cached token for `key` with at least a minute left, if there is one.
+pub fn get(key: &str) -> Option<Secret> {
+    if DISABLED.load(Ordering::Relaxed) {
+        return None;
+    }
+    let tokens = load().ok()?;
+    usable(&tokens, key, unix_now()).map(Secret::new)
+}
+
+/// Remember a token that was just signed for `key`. The cache is only an
+/// optimisation, so failing to write it (e.g. in a read-only config
+/// directory) is reported and otherwise ignored.
+pub fn put(key: &str, token: &str) {
+    if DISABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let saved = load().and_then(|mut tokens| {
+        let now = unix_now();
+        tokens.retain(|_, cached| cached.expires_at > now);
+        tokens.insert(
+            key.to_string(),
+            CachedToken {
+                token: token.to_string(),
+                expires_at: now + TOKEN_LIFETIME,
+            },
+        );
+        save(&tokens)
+    });
+    if let Err(e) = saved {
+        eprintln!("Warning: could not cache the IAM token: {:#}", e);
+    }
+}
+
+fn usable<'a>(tokens: &'a BTreeMap<String, CachedToken>, key: &str, now: u64) -> Option<&'a str> {
+    tokens
+        .get(key)
+        .filter(|cached| cached.expires_at >= now + MIN_REMAINING)
+        .map(|cached| cached.token.as_str())
+}
+
+fn load() -> Result<BTreeMap<String, CachedToken>> {
+    let path = get_token_cache_path()?;
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    let content = fs::read_to_string(path).context("Could not read the IAM token cache")?;
+    // A cache that can't be parsed is only a cache; start it again
+    Ok(serde_json::from_str(&content).unwrap_or_default())
+}
+
+fn save(tokens: &BTreeMap<String, CachedToken>) -> Result<()> {
+    let path = get_token_cache_path()?;
+    let content = serde_json::to_string_pretty(tokens).context("Could not serialize the IAM token cache")?;
+    write_private(&path, &content).map_err(|e| config_io_error(e, "write the IAM token cache", &path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_are_reused_until_a_minute_before_they_expire() {
+        let key = key("db.internal", 5432, "app", Some("prod"), None);
+        assert_eq!(key, "app@db.internal:5432 profile=prod region=-");
+
+        let signed_at = 1_700_000_000;
+        let tokens = BTreeMap::from([(
+            key.clone(),
+            CachedToken {
+                token: "token-value".to_string(),
+                expires_at: signed_at + TOKEN_LIFETIME,
+            },
+        )]);
+        assert_eq!(usable(&tokens, &key, signed_at + 5), Some("token-value"));
+        assert_eq!(usable(&tokens, &key, signed_at + TOKEN_LIFETIME - 60), Some("token-value"));
+        assert_eq!(usable(&tokens, &key, signed_at + TOKEN_LIFETIME - 59), None);
+        assert_eq!(usable(&tokens, "app@db.internal:5432 profile=- region=-", signed_at), None);
+    }
+}
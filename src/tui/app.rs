@@ -1,10 +1,25 @@
 use anyhow::{Context, Result};
+use ratatui::layout::{Position, Rect};
+use std::cell::RefCell;
+use std::cmp::Ordering;
 use std::collections::HashMap;
-use std::process::{Command, Stdio};
-
-use crate::aws::{generate_iam_token, list_aws_profiles};
-use crate::config::{load_connections, save_connections, ConnectionInfo};
-use crate::credentials::{get_password, remove_password, store_password};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::aws::{iam_token_for, list_aws_profiles};
+use crate::clipboard;
+use crate::config::{
+    clear_last_error, drop_in_source, load_connections, load_last_errors, load_last_used,
+    load_settings, most_recent, record_last_error, record_last_used, update_connections,
+    update_settings, ConnectionInfo, DefaultAction, LastError, SortField, TuiSettings,
+};
+use crate::credentials::{get_password, has_password, remove_password, store_password, verify_password};
+use crate::hook::{after_session, pre_connect};
+use crate::pgpass::{parse_pgpass, pgpass_path};
+use crate::psql::{session_env, spawn_psql, spawn_session, store_command, PsqlOptions, SSLMODES};
+use crate::redact::redact;
+use crate::runner::SystemRunner;
+use crate::tunnel;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum AppMode {
@@ -16,7 +31,13 @@ pub enum AppMode {
     Connecting,
     ConfirmDelete,
     ConfirmQuit,
+    /// Adding a connection under a name that's already taken
+    ConfirmOverwrite,
+    /// Quick pick of recently used connections, shown at startup with `--recent`
+    Recent,
     Search,
+    ConnectFailed,
+    TagEdit,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -24,6 +45,7 @@ pub enum Action {
     Connect,
     IamConnect,
     Session,
+    Export,
     Delete,
 }
 
@@ -33,21 +55,121 @@ impl Action {
             Action::Connect => "Connect (psql)",
             Action::IamConnect => "IAM Connect",
             Action::Session => "Session (shell with env vars)",
+            Action::Export => "Copy store command",
             Action::Delete => "Delete",
         }
     }
 
     pub fn available_actions(is_iam: bool) -> Vec<Action> {
         if is_iam {
-            vec![Action::IamConnect, Action::Session, Action::Delete]
+            vec![Action::IamConnect, Action::Session, Action::Export, Action::Delete]
         } else {
-            vec![Action::Connect, Action::Session, Action::Delete]
+            vec![Action::Connect, Action::Session, Action::Export, Action::Delete]
+        }
+    }
+}
+
+/// How a form field takes input and is drawn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldKind {
+    /// Free text
+    Text,
+    /// Digits only
+    Number,
+    /// Free text, masked while typed
+    Secret,
+    /// On/off, flipped with Space
+    Toggle,
+    /// Acts on Enter
+    Button,
+}
+
+/// One entry of the add/edit form.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FormField {
+    Name,
+    Host,
+    Port,
+    Database,
+    Username,
+    Iam,
+    Password,
+    Advanced,
+    Tags,
+    Sslmode,
+    Bastion,
+    Region,
+    ClientVersion,
+    Submit,
+}
+
+/// Every field in display order. Advanced fields are optional and only
+/// shown once the Advanced toggle is expanded.
+const FORM_FIELDS: &[FormField] = &[
+    FormField::Name,
+    FormField::Host,
+    FormField::Port,
+    FormField::Database,
+    FormField::Username,
+    FormField::Iam,
+    FormField::Password,
+    FormField::Advanced,
+    FormField::Tags,
+    FormField::Sslmode,
+    FormField::Bastion,
+    FormField::Region,
+    FormField::ClientVersion,
+    FormField::Submit,
+];
+
+impl FormField {
+    pub fn label(&self) -> &'static str {
+        match self {
+            FormField::Name => "Name",
+            FormField::Host => "Host",
+            FormField::Port => "Port",
+            FormField::Database => "Database",
+            FormField::Username => "Username",
+            FormField::Iam => "IAM Auth",
+            FormField::Password => "Password",
+            FormField::Advanced => "Advanced",
+            FormField::Tags => "Tags (comma-separated)",
+            FormField::Sslmode => "sslmode",
+            FormField::Bastion => "Bastion",
+            FormField::Region => "AWS region (IAM)",
+            FormField::ClientVersion => "psql major version",
+            FormField::Submit => "Submit",
+        }
+    }
+
+    pub fn kind(&self) -> FieldKind {
+        match self {
+            FormField::Port | FormField::ClientVersion => FieldKind::Number,
+            FormField::Password => FieldKind::Secret,
+            FormField::Iam | FormField::Advanced => FieldKind::Toggle,
+            FormField::Submit => FieldKind::Button,
+            _ => FieldKind::Text,
         }
     }
+
+    /// Optional fields collapsed under the Advanced toggle.
+    pub fn is_advanced(&self) -> bool {
+        matches!(
+            self,
+            FormField::Tags
+                | FormField::Sslmode
+                | FormField::Bastion
+                | FormField::Region
+                | FormField::ClientVersion
+        )
+    }
 }
 
 #[derive(Debug, Default)]
 pub struct FormState {
+    /// Editing an existing connection: the name is fixed and an empty
+    /// password keeps the stored one.
+    pub editing: bool,
     pub name: String,
     pub host: String,
     pub port: String,
@@ -55,74 +177,176 @@ pub struct FormState {
     pub username: String,
     pub password: String,
     pub iam: bool,
+    pub tags: String,
+    pub sslmode: String,
+    pub bastion: String,
+    pub region: String,
+    pub client_version: String,
+    pub show_advanced: bool,
+    /// Index into `fields()`
     pub current_field: usize,
 }
 
 impl FormState {
     pub fn reset(&mut self) {
-        self.name.clear();
-        self.host.clear();
-        self.port = "5432".to_string();
-        self.database.clear();
-        self.username.clear();
-        self.password.clear();
-        self.iam = false;
-        self.current_field = 0;
+        *self = FormState {
+            port: "5432".to_string(),
+            ..Default::default()
+        };
     }
 
-    pub fn next_field(&mut self) {
-        let max_field = if self.iam { 6 } else { 7 }; // Skip password if IAM
-        self.current_field = (self.current_field + 1).min(max_field);
+    pub fn load_connection(&mut self, name: &str, info: &ConnectionInfo) {
+        self.reset();
+        self.editing = true;
+        self.name = name.to_string();
+        self.host = info.host.clone();
+        self.port = info.port.to_string();
+        self.database = info.database.clone();
+        self.username = info.username.clone();
+        self.iam = info.iam_auth;
+        self.tags = info.tags.join(", ");
+        self.sslmode = info.sslmode.clone().unwrap_or_default();
+        self.bastion = info.bastion.clone().unwrap_or_default();
+        self.region = info.region.clone().unwrap_or_default();
+        self.client_version = info
+            .client_version
+            .map(|version| version.to_string())
+            .unwrap_or_default();
+        // Don't hide settings the connection already uses
+        self.show_advanced = FORM_FIELDS
+            .iter()
+            .any(|field| field.is_advanced() && self.value(*field).is_some_and(|v| !v.is_empty()));
+        self.current_field = 1;
+    }
+
+    /// The fields currently on screen, in order.
+    pub fn fields(&self) -> Vec<FormField> {
+        FORM_FIELDS
+            .iter()
+            .copied()
+            .filter(|field| self.show_advanced || !field.is_advanced())
+            .collect()
+    }
+
+    pub fn current(&self) -> FormField {
+        self.fields()
+            .get(self.current_field)
+            .copied()
+            .unwrap_or(FormField::Submit)
+    }
+
+    /// Shown but skipped over, like the password of an IAM connection.
+    pub fn is_disabled(&self, field: FormField) -> bool {
+        match field {
+            FormField::Name => self.editing,
+            FormField::Password => self.iam,
+            _ => false,
+        }
+    }
 
-        // Skip password field if IAM is enabled
-        if self.iam && self.current_field == 6 {
-            self.current_field = 7; // Jump to submit
+    /// Select the field at `index`, if it can take focus.
+    pub fn select(&mut self, index: usize) {
+        if let Some(&field) = self.fields().get(index)
+            && !(field == FormField::Password && self.iam)
+        {
+            self.current_field = index;
+        }
+    }
+
+    pub fn next_field(&mut self) {
+        let fields = self.fields();
+        if let Some(offset) = fields
+            .iter()
+            .skip(self.current_field + 1)
+            .position(|field| !(*field == FormField::Password && self.iam))
+        {
+            self.current_field += offset + 1;
         }
     }
 
     pub fn prev_field(&mut self) {
-        if self.current_field > 0 {
-            self.current_field -= 1;
+        let fields = self.fields();
+        if let Some(index) = fields[..self.current_field.min(fields.len())]
+            .iter()
+            .rposition(|field| !(*field == FormField::Password && self.iam))
+        {
+            self.current_field = index;
+        }
+    }
 
-            // Skip password field if IAM is enabled
-            if self.iam && self.current_field == 6 {
-                self.current_field = 5; // Jump back to IAM checkbox
-            }
+    pub fn value(&self, field: FormField) -> Option<&String> {
+        match field {
+            FormField::Name => Some(&self.name),
+            FormField::Host => Some(&self.host),
+            FormField::Port => Some(&self.port),
+            FormField::Database => Some(&self.database),
+            FormField::Username => Some(&self.username),
+            FormField::Password => Some(&self.password),
+            FormField::Tags => Some(&self.tags),
+            FormField::Sslmode => Some(&self.sslmode),
+            FormField::Bastion => Some(&self.bastion),
+            FormField::Region => Some(&self.region),
+            FormField::ClientVersion => Some(&self.client_version),
+            FormField::Iam | FormField::Advanced | FormField::Submit => None,
+        }
+    }
+
+    fn value_mut(&mut self, field: FormField) -> Option<&mut String> {
+        if self.is_disabled(field) {
+            return None;
+        }
+        match field {
+            FormField::Name => Some(&mut self.name),
+            FormField::Host => Some(&mut self.host),
+            FormField::Port => Some(&mut self.port),
+            FormField::Database => Some(&mut self.database),
+            FormField::Username => Some(&mut self.username),
+            FormField::Password => Some(&mut self.password),
+            FormField::Tags => Some(&mut self.tags),
+            FormField::Sslmode => Some(&mut self.sslmode),
+            FormField::Bastion => Some(&mut self.bastion),
+            FormField::Region => Some(&mut self.region),
+            FormField::ClientVersion => Some(&mut self.client_version),
+            FormField::Iam | FormField::Advanced | FormField::Submit => None,
+        }
+    }
+
+    pub fn is_checked(&self, field: FormField) -> bool {
+        match field {
+            FormField::Iam => self.iam,
+            FormField::Advanced => self.show_advanced,
+            _ => false,
+        }
+    }
+
+    pub fn toggle(&mut self, field: FormField) {
+        match field {
+            FormField::Iam => self.iam = !self.iam,
+            // Advanced fields come after the toggle, so the selection stays put
+            FormField::Advanced => self.show_advanced = !self.show_advanced,
+            _ => {}
         }
     }
 
     pub fn handle_char(&mut self, c: char) {
-        match self.current_field {
-            0 => self.name.push(c),
-            1 => self.host.push(c),
-            2 => {
-                if c.is_ascii_digit() {
-                    self.port.push(c);
+        let field = self.current();
+        match field.kind() {
+            FieldKind::Toggle if c == ' ' => self.toggle(field),
+            FieldKind::Number if !c.is_ascii_digit() => {}
+            _ => {
+                if let Some(value) = self.value_mut(field) {
+                    value.push(c);
                 }
             }
-            3 => self.database.push(c),
-            4 => self.username.push(c),
-            6 if !self.iam => self.password.push(c),
-            _ => {}
         }
     }
 
     pub fn handle_backspace(&mut self) {
-        match self.current_field {
-            0 => { self.name.pop(); }
-            1 => { self.host.pop(); }
-            2 => { self.port.pop(); }
-            3 => { self.database.pop(); }
-            4 => { self.username.pop(); }
-            6 if !self.iam => { self.password.pop(); }
-            _ => {}
+        if let Some(value) = self.value_mut(self.current()) {
+            value.pop();
         }
     }
 
-    pub fn field_labels() -> &'static [&'static str] {
-        &["Name", "Host", "Port", "Database", "Username", "IAM Auth", "Password", "Submit"]
-    }
-
     pub fn validate(&self) -> Result<()> {
         if self.name.is_empty() {
             anyhow::bail!("Name is required");
@@ -139,9 +363,12 @@ impl FormState {
         if self.username.is_empty() {
             anyhow::bail!("Username is required");
         }
-        if !self.iam && self.password.is_empty() {
+        if !self.iam && !self.editing && self.password.is_empty() {
             anyhow::bail!("Password is required for non-IAM connections");
         }
+        if !self.sslmode.is_empty() && !SSLMODES.contains(&self.sslmode.as_str()) {
+            anyhow::bail!("sslmode must be one of {}", SSLMODES.join(", "));
+        }
         Ok(())
     }
 }
@@ -149,15 +376,71 @@ impl FormState {
 pub enum PendingAction {
     Psql(Box<dyn FnOnce() -> Result<()>>),
     IamConnect {
-        connection_info: ConnectionInfo,
+        name: String,
+        connection_info: Box<ConnectionInfo>,
+        profile: Option<String>,
+    },
+    SsoLogin {
         profile: Option<String>,
     },
 }
 
+/// The connect or session action most recently launched, kept so that a
+/// failure can be retried without navigating back to it.
+#[derive(Debug, Clone)]
+pub struct ConnectAttempt {
+    pub name: String,
+    pub action: Action,
+    pub profile: Option<String>,
+}
+
+/// A scrollable region as last rendered: where its rows start and which
+/// item is drawn on the first row.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RowArea {
+    pub rows: Rect,
+    pub offset: usize,
+    /// An item drawn taller than one line (an expanded row), and its height
+    pub tall: Option<(usize, u16)>,
+}
+
+impl RowArea {
+    /// Index of the item drawn under `(column, row)`, if any.
+    pub fn item_at(&self, column: u16, row: u16) -> Option<usize> {
+        if !self.rows.contains(Position::new(column, row)) {
+            return None;
+        }
+        let mut line = (row - self.rows.y) as usize;
+        if let Some((tall, height)) = self.tall
+            && let Some(tall_line) = tall.checked_sub(self.offset)
+            && line > tall_line
+        {
+            let height = height as usize;
+            if line < tall_line + height {
+                return Some(tall);
+            }
+            line -= height - 1;
+        }
+        Some(self.offset + line)
+    }
+}
+
+/// Screen regions recorded while drawing so mouse clicks can be mapped back
+/// to what was rendered under them.
+#[derive(Debug, Default)]
+pub struct HitAreas {
+    pub connection_rows: Option<RowArea>,
+    pub action_popup: Option<Rect>,
+    pub action_rows: Option<RowArea>,
+    pub form_fields: Vec<Rect>,
+}
+
 pub struct App {
     pub connections: HashMap<String, ConnectionInfo>,
     pub connection_names: Vec<String>,
     pub selected_index: usize,
+    /// The selected row also shows host, port, database and user
+    pub show_details: bool,
     pub mode: AppMode,
     pub selected_action: usize,
     pub form_state: FormState,
@@ -165,6 +448,8 @@ pub struct App {
     pub selected_profile: usize,
     pub status_message: Option<String>,
     pub should_quit: bool,
+    /// Set when the TUI quit on its own after the idle timeout
+    pub idle_timed_out: bool,
     pub pending_action: Option<PendingAction>,
     pub search_query: String,
     pub search_matches: Vec<usize>,
@@ -172,20 +457,37 @@ pub struct App {
     pub profile_search_query: String,
     pub profile_search_matches: Vec<usize>,
     pub profile_search_active: bool,
+    pub last_attempt: Option<ConnectAttempt>,
+    pub connect_error: Option<String>,
+    pub settings: TuiSettings,
+    pub hit_areas: RefCell<HitAreas>,
+    /// Keyring presence for password connections, probed lazily as they
+    /// are selected (`None` when the keyring couldn't be read)
+    pub password_present: HashMap<String, Option<bool>>,
+    /// Comma-separated tags being edited for the selected connection
+    pub tag_input: String,
+    /// `.pgpass` file offered for import on the empty-state screen
+    pub pgpass_file: Option<PathBuf>,
+    /// Most recent connect failure per connection, shown under the list
+    pub last_errors: HashMap<String, LastError>,
+    /// Connections offered by the recent quick pick, with when each was last used
+    pub recent: Vec<(String, u64)>,
+    pub selected_recent: usize,
 }
 
 impl App {
     pub fn new() -> Result<Self> {
         let connections = load_connections()?;
-        let mut connection_names: Vec<String> = connections.keys().cloned().collect();
-        connection_names.sort();
+        let connection_names: Vec<String> = connections.keys().cloned().collect();
 
         let aws_profiles = list_aws_profiles();
+        let settings = load_settings()?.tui;
 
-        Ok(Self {
+        let mut app = Self {
             connections,
             connection_names,
             selected_index: 0,
+            show_details: false,
             mode: AppMode::List,
             selected_action: 0,
             form_state: FormState::default(),
@@ -193,6 +495,7 @@ impl App {
             selected_profile: 0,
             status_message: None,
             should_quit: false,
+            idle_timed_out: false,
             pending_action: None,
             search_query: String::new(),
             search_matches: Vec::new(),
@@ -200,13 +503,27 @@ impl App {
             profile_search_query: String::new(),
             profile_search_matches: Vec::new(),
             profile_search_active: false,
-        })
+            last_attempt: None,
+            connect_error: None,
+            settings,
+            hit_areas: RefCell::new(HitAreas::default()),
+            password_present: HashMap::new(),
+            tag_input: String::new(),
+            pgpass_file: pgpass_path().filter(|path| path.is_file()),
+            // Only a debugging aid, so an unreadable file shouldn't keep the TUI from starting
+            last_errors: load_last_errors().unwrap_or_default(),
+            recent: Vec::new(),
+            selected_recent: 0,
+        };
+        app.sort_connections();
+        Ok(app)
     }
 
     pub fn reload_connections(&mut self) -> Result<()> {
         self.connections = load_connections()?;
         self.connection_names = self.connections.keys().cloned().collect();
-        self.connection_names.sort();
+        self.sort_connections();
+        self.password_present.clear();
 
         // Adjust selected index if needed
         if self.selected_index >= self.connection_names.len() && !self.connection_names.is_empty() {
@@ -215,12 +532,145 @@ impl App {
         Ok(())
     }
 
+    /// Order `connection_names` by the current sort, keeping the same
+    /// connection selected.
+    fn sort_connections(&mut self) {
+        let selected = self.connection_names.get(self.selected_index).cloned();
+        let sort = self.settings.sort;
+        let connections = &self.connections;
+        self.connection_names.sort_by(|a, b| {
+            let (x, y) = (&connections[a], &connections[b]);
+            let ordering = match sort.field {
+                SortField::Name => Ordering::Equal,
+                SortField::Host => x.host.cmp(&y.host),
+                SortField::Database => x.database.cmp(&y.database),
+            }
+            .then_with(|| a.cmp(b));
+            if sort.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+
+        if let Some(index) = selected
+            .and_then(|name| self.connection_names.iter().position(|n| *n == name))
+        {
+            self.selected_index = index;
+        }
+        if !self.search_query.is_empty() {
+            let query = self.search_query.to_lowercase();
+            self.search_matches = self
+                .connection_names
+                .iter()
+                .enumerate()
+                .filter(|(_, name)| name.to_lowercase().contains(&query))
+                .map(|(i, _)| i)
+                .collect();
+            self.search_match_index = 0;
+        }
+    }
+
+    /// Start with a quick pick of the `count` most recently used connections.
+    /// Goes straight to the list if nothing has been used yet.
+    pub fn show_recent(&mut self, count: usize) {
+        // Like last errors, a convenience that shouldn't stop the TUI starting
+        let last_used = load_last_used().unwrap_or_default();
+        self.recent = most_recent(&last_used, &self.connections, count)
+            .into_iter()
+            .map(|(name, at)| (name.clone(), at))
+            .collect();
+        self.selected_recent = 0;
+        if !self.recent.is_empty() {
+            self.mode = AppMode::Recent;
+        }
+    }
+
+    pub fn next_recent(&mut self) {
+        if !self.recent.is_empty() {
+            self.selected_recent = (self.selected_recent + 1) % self.recent.len();
+        }
+    }
+
+    pub fn prev_recent(&mut self) {
+        if !self.recent.is_empty() {
+            self.selected_recent = (self.selected_recent + self.recent.len() - 1) % self.recent.len();
+        }
+    }
+
+    /// Connect to the picked recent connection, through the IAM flow for
+    /// IAM connections. The list stays selected on it afterwards.
+    pub fn connect_recent(&mut self) -> Result<()> {
+        self.mode = AppMode::List;
+        let Some((name, _)) = self.recent.get(self.selected_recent) else {
+            return Ok(());
+        };
+        let Some(index) = self.connection_names.iter().position(|n| n == name) else {
+            return Ok(());
+        };
+        self.selected_index = index;
+
+        let action = if self.connections[name].iam_auth {
+            Action::IamConnect
+        } else {
+            Action::Connect
+        };
+        self.selected_action = self
+            .available_actions()
+            .iter()
+            .position(|a| *a == action)
+            .unwrap_or(0);
+        self.execute_action()
+    }
+
+    /// Sort by the next field (name, host, database), keeping the direction.
+    pub fn cycle_sort_field(&mut self) {
+        self.settings.sort.field = self.settings.sort.field.next();
+        self.apply_sort();
+    }
+
+    pub fn reverse_sort(&mut self) {
+        self.settings.sort.descending = !self.settings.sort.descending;
+        self.apply_sort();
+    }
+
+    fn apply_sort(&mut self) {
+        self.sort_connections();
+        let sort = self.settings.sort;
+        let saved = update_settings(|settings| {
+            settings.tui.sort = sort;
+            Ok(())
+        });
+        if let Err(e) = saved {
+            self.status_message = Some(format!("Error: Could not save the sort order: {}", e));
+        }
+    }
+
     pub fn selected_connection(&self) -> Option<(&String, &ConnectionInfo)> {
         self.connection_names
             .get(self.selected_index)
             .and_then(|name| self.connections.get(name).map(|info| (name, info)))
     }
 
+    /// Check the keyring for the selected connection's password, once.
+    /// Only the selected entry is probed since keyring access can be slow
+    /// or prompt the user.
+    pub fn probe_selected_password(&mut self) {
+        let Some((name, info)) = self.selected_connection() else {
+            return;
+        };
+        if info.iam_auth || self.password_present.contains_key(name) {
+            return;
+        }
+        let name = name.clone();
+        let present = has_password(&name).ok();
+        self.password_present.insert(name, present);
+    }
+
+    pub fn toggle_details(&mut self) {
+        self.show_details = !self.show_details;
+    }
+
     pub fn next_connection(&mut self) {
         if !self.connection_names.is_empty() {
             self.selected_index = (self.selected_index + 1) % self.connection_names.len();
@@ -243,6 +693,33 @@ impl App {
         }
     }
 
+    /// Handle Enter on the list: run the configured default action, or
+    /// open the actions popup.
+    pub fn activate_selected(&mut self) -> Result<()> {
+        let Some((_, info)) = self.selected_connection() else {
+            return Ok(());
+        };
+
+        let action = match self.settings.default_action {
+            DefaultAction::Menu => {
+                self.mode = AppMode::Actions;
+                self.selected_action = 0;
+                return Ok(());
+            }
+            // IAM connections can't use plain psql, so fall back to the IAM flow (and vice versa)
+            DefaultAction::Connect | DefaultAction::IamConnect if info.iam_auth => Action::IamConnect,
+            DefaultAction::Connect | DefaultAction::IamConnect => Action::Connect,
+            DefaultAction::Session => Action::Session,
+        };
+
+        self.selected_action = self
+            .available_actions()
+            .iter()
+            .position(|a| *a == action)
+            .unwrap_or(0);
+        self.execute_action()
+    }
+
     pub fn next_action(&mut self) {
         let actions = self.available_actions();
         if !actions.is_empty() {
@@ -290,6 +767,39 @@ impl App {
         }
     }
 
+    /// Tab in the search bar: extend the query to the longest prefix all
+    /// matches share, like shell completion. When that adds nothing, complete
+    /// to the selected match's full name instead.
+    pub fn complete_search(&mut self) {
+        let Some(&selected) = self.search_matches.get(self.search_match_index) else {
+            return;
+        };
+        let matches: Vec<&str> = self
+            .search_matches
+            .iter()
+            .map(|&i| self.connection_names[i].as_str())
+            .collect();
+        let prefix = common_prefix(&matches);
+
+        let query = self.search_query.to_lowercase();
+        let completion = if prefix.chars().count() > query.chars().count() && prefix.to_lowercase().starts_with(&query) {
+            prefix.to_string()
+        } else {
+            self.connection_names[selected].clone()
+        };
+        if completion == self.search_query {
+            return;
+        }
+
+        self.search_query = completion;
+        self.update_search_matches();
+        // Stay on the completed connection rather than the first match
+        if let Some(position) = self.search_matches.iter().position(|&i| i == selected) {
+            self.search_match_index = position;
+            self.selected_index = selected;
+        }
+    }
+
     pub fn next_match(&mut self) {
         if !self.search_matches.is_empty() {
             self.search_match_index = (self.search_match_index + 1) % self.search_matches.len();
@@ -345,18 +855,41 @@ impl App {
         let name = name.clone();
         let info = info.clone();
 
+        if let Some(action @ (Action::Connect | Action::Session)) = action {
+            self.last_attempt = Some(ConnectAttempt {
+                name: name.clone(),
+                action,
+                profile: None,
+            });
+        }
+
+        let info = match action {
+            Some(Action::Connect | Action::Session) => match info.resolve_env() {
+                Ok(info) => info,
+                Err(e) => {
+                    self.mode = AppMode::List;
+                    self.connect_failed(e.to_string());
+                    return Ok(());
+                }
+            },
+            _ => info,
+        };
+
         match action {
             Some(Action::Connect) => {
                 self.mode = AppMode::List;
                 match get_password(&name) {
                     Ok(password) => {
                         self.pending_action = Some(PendingAction::Psql(Box::new(move || {
-                            spawn_psql(&info, &password)
+                            pre_connect(&SystemRunner, &name, &info)?;
+                            let (tunneled, _tunnel) = tunnel::open_for(&info)?;
+                            spawn_psql(&SystemRunner, &name, &tunneled, password.expose(), &PsqlOptions::default())
+                                .map(|elapsed| after_session(&name, &info, elapsed))
                         })));
                     }
                     Err(e) => {
-                        self.status_message = Some(format!(
-                            "Error: Could not retrieve password for '{}': {}",
+                        self.connect_failed(format!(
+                            "Could not retrieve password for '{}': {}",
                             name, e
                         ));
                     }
@@ -371,8 +904,8 @@ impl App {
                 self.mode = AppMode::List;
 
                 let password_result = if info.iam_auth {
-                    // For IAM connections, generate token with default profile
-                    generate_iam_token(&info.host, info.port, &info.username, None)
+                    // For IAM connections, use the connection's profiles or the default
+                    iam_token_for(&SystemRunner, &info, None, false).map(|(token, _)| token)
                 } else {
                     get_password(&name)
                 };
@@ -380,17 +913,27 @@ impl App {
                 match password_result {
                     Ok(password) => {
                         self.pending_action = Some(PendingAction::Psql(Box::new(move || {
-                            spawn_session(&info, &password)
+                            let (tunneled, _tunnel) = tunnel::open_for(&info)?;
+                            spawn_session(&SystemRunner, &session_env(&tunneled, &password))
+                                .map(|elapsed| after_session(&name, &info, elapsed))
                         })));
                     }
                     Err(e) => {
-                        self.status_message = Some(format!(
-                            "Error: Could not retrieve credentials for '{}': {}",
+                        self.connect_failed(format!(
+                            "Could not retrieve credentials for '{}': {}",
                             name, e
                         ));
                     }
                 }
             }
+            Some(Action::Export) => {
+                self.mode = AppMode::List;
+                // The stored definition, so ${VAR} references are kept
+                self.status_message = Some(match clipboard::copy(&store_command(&name, &info)) {
+                    Ok(via) => format!("Copied the store command for '{}' (via {})", name, via),
+                    Err(e) => format!("Error: Could not copy the store command: {}", e),
+                });
+            }
             Some(Action::Delete) => {
                 self.mode = AppMode::ConfirmDelete;
             }
@@ -400,13 +943,28 @@ impl App {
     }
 
     pub fn connect_with_profile(&mut self) -> Result<()> {
-        let Some((_name, info)) = self.selected_connection() else {
+        let Some((name, info)) = self.selected_connection() else {
             return Ok(());
         };
+        let name = name.clone();
         let info = info.clone();
         let profile = self.aws_profiles.get(self.selected_profile).cloned();
 
+        self.last_attempt = Some(ConnectAttempt {
+            name: name.clone(),
+            action: Action::IamConnect,
+            profile: profile.clone(),
+        });
+
         self.mode = AppMode::List;
+        let info = match info.resolve_env() {
+            Ok(info) => info,
+            Err(e) => {
+                self.connect_failed(e.to_string());
+                return Ok(());
+            }
+        };
+
         self.status_message = Some(format!(
             "Connecting with profile '{}'...",
             profile.as_deref().unwrap_or("default")
@@ -414,122 +972,432 @@ impl App {
 
         // Defer IAM token generation to the spawn handler so we can show loading state
         self.pending_action = Some(PendingAction::IamConnect {
-            connection_info: info,
+            name,
+            connection_info: Box::new(info),
             profile,
         });
 
         Ok(())
     }
 
-    pub fn retry_iam_connect(&mut self, info: ConnectionInfo, profile: Option<String>) {
+    pub fn retry_iam_connect(&mut self, name: String, info: ConnectionInfo, profile: Option<String>) {
         self.status_message = Some("Retrying IAM connection...".to_string());
         self.pending_action = Some(PendingAction::IamConnect {
-            connection_info: info,
+            name,
+            connection_info: Box::new(info),
             profile,
         });
     }
 
+    /// Quit after the idle timeout, dropping anything typed into the form
+    /// (such as a password) and other in-progress input first.
+    pub fn idle_quit(&mut self) {
+        self.form_state = FormState::default();
+        self.tag_input.clear();
+        self.search_query.clear();
+        self.profile_search_query.clear();
+        self.connect_error = None;
+        self.last_attempt = None;
+        self.status_message = None;
+        self.pending_action = None;
+        self.mode = AppMode::List;
+        self.should_quit = true;
+        self.idle_timed_out = true;
+    }
+
+    /// Record a failed connect. Shows the retry prompt when the failure
+    /// belongs to a tracked attempt, otherwise just a status message.
+    pub fn connect_failed(&mut self, error: String) {
+        let error = redact(&error);
+        if let Some(attempt) = &self.last_attempt {
+            let last_error = LastError::now(error.clone());
+            let _ = record_last_error(&attempt.name, &last_error);
+            self.last_errors.insert(attempt.name.clone(), last_error);
+            self.connect_error = Some(error);
+            self.mode = AppMode::ConnectFailed;
+        } else {
+            self.status_message = Some(format!("Error: {}", error));
+        }
+    }
+
+    /// Forget the previous failure of the attempt that just worked.
+    pub fn connect_succeeded(&mut self) {
+        let Some(attempt) = &self.last_attempt else {
+            return;
+        };
+        if self.last_errors.remove(&attempt.name).is_some() {
+            let _ = clear_last_error(&attempt.name);
+        }
+        let _ = record_last_used(&attempt.name);
+    }
+
+    /// Whether the failed attempt can be helped by an AWS SSO login.
+    pub fn failed_attempt_uses_iam(&self) -> bool {
+        self.last_attempt
+            .as_ref()
+            .and_then(|attempt| self.connections.get(&attempt.name))
+            .is_some_and(|info| info.iam_auth)
+    }
+
+    fn select_attempted_connection(&mut self) -> Option<(ConnectAttempt, ConnectionInfo)> {
+        self.connect_error = None;
+        self.mode = AppMode::List;
+
+        let attempt = self.last_attempt.clone()?;
+        let Some(index) = self.connection_names.iter().position(|n| n == &attempt.name) else {
+            self.status_message = Some(format!(
+                "Error: Connection '{}' no longer exists",
+                attempt.name
+            ));
+            return None;
+        };
+        self.selected_index = index;
+        let info = self.connections[&attempt.name].clone();
+        Some((attempt, info))
+    }
+
+    pub fn retry_last_attempt(&mut self) -> Result<()> {
+        let Some((attempt, info)) = self.select_attempted_connection() else {
+            return Ok(());
+        };
+
+        match attempt.action {
+            Action::IamConnect => match info.resolve_env() {
+                Ok(info) => self.retry_iam_connect(attempt.name, info, attempt.profile),
+                Err(e) => self.connect_failed(e.to_string()),
+            },
+            action => {
+                self.selected_action = self
+                    .available_actions()
+                    .iter()
+                    .position(|a| *a == action)
+                    .unwrap_or(0);
+                self.execute_action()?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn edit_last_attempt(&mut self) {
+        if let Some((attempt, info)) = self.select_attempted_connection() {
+            self.form_state.load_connection(&attempt.name, &info);
+            self.mode = AppMode::AddForm;
+        }
+    }
+
+    /// Reopen the profile selector for the failed IAM connection, with the
+    /// profile after the one that failed highlighted.
+    pub fn choose_profile_for_last_attempt(&mut self) {
+        let Some((attempt, _)) = self.select_attempted_connection() else {
+            return;
+        };
+        let failed = attempt
+            .profile
+            .as_ref()
+            .and_then(|profile| self.aws_profiles.iter().position(|p| p == profile));
+        self.selected_profile = match failed {
+            Some(index) if !self.aws_profiles.is_empty() => (index + 1) % self.aws_profiles.len(),
+            _ => 0,
+        };
+        self.clear_profile_search();
+        self.mode = AppMode::ProfileSelector;
+    }
+
+    pub fn sso_login_for_last_attempt(&mut self) {
+        let profile = self
+            .last_attempt
+            .as_ref()
+            .and_then(|attempt| attempt.profile.clone());
+        self.connect_error = None;
+        self.mode = AppMode::List;
+        self.pending_action = Some(PendingAction::SsoLogin { profile });
+    }
+
+    /// Add a connection for every concrete entry in the `.pgpass` file that
+    /// isn't already stored, keeping its password in the keyring.
+    pub fn import_pgpass(&mut self) -> Result<()> {
+        let Some(path) = self.pgpass_file.clone() else {
+            return Ok(());
+        };
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Could not read {}", path.display()))?;
+
+        let mut imported = 0;
+        update_connections(|connections| {
+            for entry in parse_pgpass(&content) {
+                let info = entry.to_connection();
+                if connections.values().any(|existing| {
+                    existing.host == info.host
+                        && existing.port == info.port
+                        && existing.database == info.database
+                        && existing.username == info.username
+                }) {
+                    continue;
+                }
+
+                let name = entry.suggest_name(connections);
+                store_password(&name, &entry.password)?;
+                connections.insert(name, info);
+                imported += 1;
+            }
+            Ok(())
+        })?;
+        self.reload_connections()?;
+        self.status_message = Some(format!(
+            "Imported {} connection(s) from {}",
+            imported,
+            path.display()
+        ));
+        Ok(())
+    }
+
+    pub fn start_tag_edit(&mut self) {
+        let Some((_, info)) = self.selected_connection() else {
+            return;
+        };
+        self.tag_input = info.tags.join(", ");
+        self.mode = AppMode::TagEdit;
+    }
+
+    /// Save the edited tags onto the selected connection.
+    pub fn submit_tag_edit(&mut self) -> Result<()> {
+        let Some((name, _)) = self.selected_connection() else {
+            return Ok(());
+        };
+        let name = name.clone();
+
+        let tags = parse_tags(&self.tag_input);
+        update_connections(|connections| {
+            if let Some(info) = connections.get_mut(&name) {
+                info.tags = tags.clone();
+            }
+            Ok(())
+        })?;
+        if let Some(info) = self.connections.get_mut(&name) {
+            info.tags = tags;
+        }
+
+        self.mode = AppMode::List;
+        self.status_message = Some(format!("Tags updated for '{}'", name));
+        Ok(())
+    }
+
     pub fn delete_selected_connection(&mut self) -> Result<()> {
         let Some((name, _)) = self.selected_connection() else {
             return Ok(());
         };
         let name = name.clone();
 
+        if let Some(path) = drop_in_source(&name)? {
+            self.status_message = Some(format!(
+                "Error: '{}' is defined in {}; remove it from that file instead",
+                name,
+                path.display()
+            ));
+            return Ok(());
+        }
+
+        update_connections(|connections| {
+            connections.remove(&name);
+            Ok(())
+        })?;
         self.connections.remove(&name);
-        save_connections(&self.connections)?;
 
         // Try to remove password, but don't fail if it doesn't exist
         let _ = remove_password(&name);
+        self.last_errors.remove(&name);
+        let _ = clear_last_error(&name);
 
         self.reload_connections()?;
         self.status_message = Some(format!("Connection '{}' deleted", name));
         Ok(())
     }
 
+    /// Whether submitting the add form would replace a different connection
+    /// that already has its name. Editing a connection replaces itself.
+    pub fn submit_would_overwrite(&self) -> bool {
+        !self.form_state.editing && self.connections.contains_key(&self.form_state.name)
+    }
+
     pub fn submit_form(&mut self) -> Result<()> {
         self.form_state.validate()?;
 
         let port: u16 = self.form_state.port.parse()
             .context("Invalid port number")?;
 
-        let info = ConnectionInfo {
-            host: self.form_state.host.clone(),
-            port,
-            database: self.form_state.database.clone(),
-            username: self.form_state.username.clone(),
-            iam_auth: self.form_state.iam,
-        };
-
         let name = self.form_state.name.clone();
         let password = self.form_state.password.clone();
 
+        // Start from the entry being edited so fields the form doesn't show
+        // survive; a confirmed replace starts from scratch like a new one
+        let replaced = !self.form_state.editing && self.connections.contains_key(&name);
+        let mut info = if self.form_state.editing {
+            self.connections.get(&name).cloned().unwrap_or_default()
+        } else {
+            ConnectionInfo::default()
+        };
+        info.host = self.form_state.host.clone();
+        info.port = port;
+        info.database = self.form_state.database.clone();
+        info.username = self.form_state.username.clone();
+        info.iam_auth = self.form_state.iam;
+        info.tags = parse_tags(&self.form_state.tags);
+        info.sslmode = non_empty(&self.form_state.sslmode);
+        info.bastion = non_empty(&self.form_state.bastion);
+        info.region = non_empty(&self.form_state.region);
+        if info.region.is_some() && !info.iam_auth {
+            anyhow::bail!("An AWS region is only used with IAM auth");
+        }
+        info.client_version = non_empty(&self.form_state.client_version)
+            .map(|version| version.parse())
+            .transpose()
+            .context("Invalid psql major version")?;
+        if let Some(bastion) = &info.bastion
+            && !load_settings()?.bastions.contains_key(bastion)
+        {
+            anyhow::bail!("Bastion '{}' not found. Add it with 'pg-vault bastion add {}'.", bastion, bastion);
+        }
+        // A password typed here goes to the keyring, replacing any plaintext one
+        if self.form_state.iam || !password.is_empty() {
+            info.plaintext_password = false;
+        }
+
+        update_connections(|connections| {
+            connections.insert(name.clone(), info.clone());
+            Ok(())
+        })?;
         self.connections.insert(name.clone(), info);
-        save_connections(&self.connections)?;
 
-        if !self.form_state.iam {
+        let stores_password = !self.form_state.iam && !password.is_empty();
+        if stores_password {
             store_password(&name, &password)?;
+        } else if replaced && self.form_state.iam {
+            // The replaced connection's password doesn't belong to this one
+            let _ = remove_password(&name);
+        }
+        if replaced && self.last_errors.remove(&name).is_some() {
+            let _ = clear_last_error(&name);
         }
 
         self.reload_connections()?;
         self.mode = AppMode::List;
         self.form_state.reset();
+
+        // The connection is saved either way; a keyring that lost the
+        // password still needs to be reported
+        if stores_password {
+            verify_password(&name, &password)?;
+        }
         Ok(())
     }
 }
 
-fn spawn_psql(info: &ConnectionInfo, password: &str) -> Result<()> {
-    let mut cmd = Command::new("psql");
-    cmd.arg(format!(
-        "postgres://{}:{}@{}:{}/{}",
-        info.username,
-        password,
-        info.host,
-        info.port,
-        info.database
-    ))
-    .env("PGPASSWORD", password)
-    .stdin(Stdio::inherit())
-    .stdout(Stdio::inherit())
-    .stderr(Stdio::inherit());
-
-    let status = cmd.status().context(
-        "Failed to execute psql command. Make sure psql is installed and in your PATH.",
-    )?;
-
-    if !status.success() {
-        anyhow::bail!("psql exited with error code: {:?}", status.code());
-    }
-    Ok(())
+fn non_empty(value: &str) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
 }
 
-fn spawn_session(info: &ConnectionInfo, password: &str) -> Result<()> {
-    let shell = std::env::var("SHELL").unwrap_or_else(|_| "/bin/bash".to_string());
-
-    let mut cmd = Command::new(&shell);
-    cmd.env("PGHOST", &info.host)
-        .env("PGPORT", info.port.to_string())
-        .env("PGDATABASE", &info.database)
-        .env("PGUSER", &info.username)
-        .env("PGPASSWORD", password)
-        .env(
-            "DATABASE_URL",
-            format!(
-                "postgres://{}:{}@{}:{}/{}",
-                info.username,
-                password,
-                info.host,
-                info.port,
-                info.database
-            ),
-        )
-        .stdin(Stdio::inherit())
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit());
+/// The longest prefix every one of `names` starts with.
+fn common_prefix<'a>(names: &[&'a str]) -> &'a str {
+    let Some((first, rest)) = names.split_first() else {
+        return "";
+    };
+    let mut len = first.len();
+    for name in rest {
+        len = first
+            .char_indices()
+            .zip(name.chars())
+            .take_while(|((_, a), b)| a == b)
+            .last()
+            .map_or(0, |((i, a), _)| i + a.len_utf8())
+            .min(len);
+    }
+    &first[..len]
+}
+
+/// Split comma-separated input into trimmed, de-duplicated tags.
+fn parse_tags(input: &str) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for tag in input.split(',').map(str::trim).filter(|tag| !tag.is_empty()) {
+        if !tags.iter().any(|existing| existing == tag) {
+            tags.push(tag.to_string());
+        }
+    }
+    tags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let status = cmd.status().context("Failed to start shell session")?;
+    #[test]
+    fn tag_input_is_trimmed_and_deduplicated() {
+        assert_eq!(parse_tags(" prod, billing,,prod ,"), vec!["prod", "billing"]);
+        assert!(parse_tags("  ").is_empty());
+    }
 
-    if !status.success() {
-        anyhow::bail!("Shell session exited with error code: {:?}", status.code());
+    #[test]
+    fn form_skips_iam_password_and_expands_advanced_fields() {
+        let mut form = FormState::default();
+        form.reset();
+        form.current_field = 5;
+        assert_eq!(form.current(), FormField::Iam);
+
+        form.handle_char(' ');
+        assert!(form.iam);
+        form.next_field();
+        assert_eq!(form.current(), FormField::Advanced);
+        form.prev_field();
+        assert_eq!(form.current(), FormField::Iam);
+
+        form.next_field();
+        form.next_field();
+        assert_eq!(form.current(), FormField::Submit);
+
+        form.prev_field();
+        form.toggle(FormField::Advanced);
+        form.next_field();
+        assert_eq!(form.current(), FormField::Tags);
+        form.handle_char('x');
+        assert_eq!(form.tags, "x");
+
+        // Number fields ignore anything but digits
+        form.current_field = form.fields().iter().position(|f| *f == FormField::ClientVersion).unwrap();
+        "1a6".chars().for_each(|c| form.handle_char(c));
+        assert_eq!(form.client_version, "16");
+    }
+
+    #[test]
+    fn common_prefix_stops_at_the_first_difference() {
+        assert_eq!(common_prefix(&["prod-orders", "prod-billing"]), "prod-");
+        assert_eq!(common_prefix(&["prod", "prod-replica"]), "prod");
+        assert_eq!(common_prefix(&["café-a", "café-b"]), "café-");
+        assert_eq!(common_prefix(&["staging", "prod"]), "");
+        assert_eq!(common_prefix(&[]), "");
+    }
+
+    #[test]
+    fn row_area_maps_clicks_through_scroll_offset() {
+        let area = RowArea {
+            rows: Rect::new(2, 5, 20, 4),
+            offset: 10,
+            tall: None,
+        };
+        assert_eq!(area.item_at(3, 5), Some(10));
+        assert_eq!(area.item_at(21, 8), Some(13));
+        assert_eq!(area.item_at(3, 9), None);
+        assert_eq!(area.item_at(1, 6), None);
+    }
+
+    #[test]
+    fn row_area_counts_every_line_of_an_expanded_row() {
+        // Item 11 takes three lines: 6, 7 and 8
+        let area = RowArea {
+            rows: Rect::new(0, 5, 20, 6),
+            offset: 10,
+            tall: Some((11, 3)),
+        };
+        let items: Vec<_> = (5..11).map(|row| area.item_at(0, row)).collect();
+        assert_eq!(items, [Some(10), Some(11), Some(11), Some(11), Some(12), Some(13)]);
     }
-    Ok(())
 }
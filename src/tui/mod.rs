@@ -2,28 +2,58 @@ pub mod app;
 pub mod ui;
 pub mod widgets;
 
-use anyhow::{Context, Result};
+use anyhow::Result;
 use crossterm::{
     cursor::Show,
-    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    event::{
+        self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyEventKind,
+        KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+    },
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use signal_hook::consts::SIGINT;
 use signal_hook::flag;
 use ratatui::prelude::*;
+use ratatui::{TerminalOptions, Viewport};
 use std::io::{self, stdout, Write};
 use std::panic;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+/// How the TUI starts, from the command line.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Options {
+    /// Draw below the prompt instead of in the alternate screen
+    pub inline: bool,
+    /// Open with a quick pick of this many recently used connections
+    pub recent: Option<usize>,
+}
+
+/// Rows the TUI takes up when running inline (fewer on a shorter terminal).
+const INLINE_HEIGHT: u16 = 24;
+
+/// Whether the TUI runs inline rather than in the alternate screen. Set once
+/// by `run`; global so the panic hook can restore the terminal correctly.
+static INLINE: AtomicBool = AtomicBool::new(false);
+
+fn inline() -> bool {
+    INLINE.load(Ordering::Relaxed)
+}
 
-use app::{App, AppMode, PendingAction};
+use app::{App, AppMode, FieldKind, PendingAction};
 use ui::draw;
 
-use crate::aws::{generate_iam_token, needs_sso_login};
+use crate::aws::{generate_iam_token, iam_region, needs_sso_login, sso_login};
+use crate::hook::{after_session, pre_connect};
+use crate::psql::{spawn_psql_iam, PsqlOptions};
+use crate::runner::SystemRunner;
+use crate::tunnel;
+
+pub fn run(options: Options) -> Result<()> {
+    INLINE.store(options.inline, Ordering::Relaxed);
 
-pub fn run() -> Result<()> {
     // Set up panic hook to restore terminal on panic
     let original_hook = panic::take_hook();
     panic::set_hook(Box::new(move |panic_info| {
@@ -34,65 +64,217 @@ pub fn run() -> Result<()> {
     // Initialize terminal
     enable_raw_mode()?;
     let mut stdout = stdout();
-    execute!(stdout, EnterAlternateScreen)?;
+    if inline() {
+        execute!(stdout, EnableMouseCapture)?;
+    } else {
+        execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
+    }
     let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend)?;
+    let viewport = if inline() {
+        Viewport::Inline(INLINE_HEIGHT)
+    } else {
+        Viewport::Fullscreen
+    };
+    let mut terminal = Terminal::with_options(backend, TerminalOptions { viewport })?;
 
     // Create app state
     let mut app = App::new()?;
+    if let Some(count) = options.recent {
+        app.show_recent(count);
+    }
 
     // Main event loop
     let result = run_app(&mut terminal, &mut app);
 
+    // Leave the last frame in the scrollback and continue below it
+    if inline() {
+        let area = terminal.get_frame().area();
+        terminal.set_cursor_position(Position::new(0, area.bottom().saturating_sub(1)))?;
+    }
+
     // Restore terminal
     restore_terminal()?;
+    if inline() {
+        println!();
+    }
+
+    if app.idle_timed_out
+        && let Some(minutes) = app.settings.idle_timeout_minutes
+    {
+        println!("pg-vault closed after {} minute(s) without input.", minutes);
+    }
 
     result
 }
 
 fn restore_terminal() -> Result<()> {
     disable_raw_mode()?;
-    execute!(io::stdout(), LeaveAlternateScreen, Show)?;
+    if inline() {
+        execute!(io::stdout(), DisableMouseCapture, Show)?;
+    } else {
+        execute!(io::stdout(), DisableMouseCapture, LeaveAlternateScreen, Show)?;
+    }
+    Ok(())
+}
+
+/// Hand the terminal to a child process (psql, a shell, `aws sso login`)
+/// with a clean screen to write to.
+fn suspend_terminal<B: Backend>(terminal: &mut Terminal<B>) -> Result<()> {
+    if inline() {
+        // Erase the TUI and let the child write from where it was. Marking
+        // the cursor as being at the viewport's top makes `resume_terminal`
+        // open the next viewport below whatever the child printed.
+        terminal.clear()?;
+        let top = terminal.get_frame().area().as_position();
+        terminal.set_cursor_position(top)?;
+        restore_terminal()?;
+    } else {
+        restore_terminal()?;
+        print!("\x1B[2J\x1B[H");
+    }
+    io::stdout().flush()?;
+    Ok(())
+}
+
+fn resume_terminal<B: Backend>(terminal: &mut Terminal<B>) -> Result<()> {
+    enable_raw_mode()?;
+    if inline() {
+        execute!(io::stdout(), EnableMouseCapture)?;
+        let size = terminal.size()?;
+        terminal.resize(Rect::new(0, 0, size.width, size.height))?;
+    } else {
+        execute!(io::stdout(), EnterAlternateScreen, EnableMouseCapture)?;
+        terminal.clear()?;
+    }
     Ok(())
 }
 
 fn run_app<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> Result<()> {
+    let idle_timeout = app.settings.idle_timeout();
+    let mut last_activity = Instant::now();
+
     loop {
+        app.probe_selected_password();
         terminal.draw(|f| draw(f, app))?;
 
+        if let Some(timeout) = idle_timeout
+            && last_activity.elapsed() >= timeout
+        {
+            app.idle_quit();
+            return Ok(());
+        }
+
         // Poll for events with a timeout
         if event::poll(Duration::from_millis(100))? {
-            if let Event::Key(key) = event::read()? {
-                if key.kind == KeyEventKind::Press {
-                    // Handle Ctrl+C globally - show quit confirmation
-                    if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
-                        app.mode = AppMode::ConfirmQuit;
-                        continue;
-                    }
+            last_activity = Instant::now();
+            match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => handle_key(app, key)?,
+                Event::Mouse(mouse) => handle_mouse(app, mouse)?,
+                _ => continue,
+            }
 
-                    match app.mode {
-                        AppMode::List => handle_list_input(app, key.code)?,
-                        AppMode::Actions => handle_actions_input(app, key.code)?,
-                        AppMode::AddForm => handle_form_input(app, key.code),
-                        AppMode::ProfileSelector => handle_profile_input(app, key.code)?,
-                        AppMode::Connecting => {}
-                        AppMode::ConfirmDelete => handle_confirm_delete_input(app, key.code)?,
-                        AppMode::ConfirmQuit => handle_confirm_quit_input(app, key.code),
-                        AppMode::Search => handle_search_input(app, key.code),
-                    }
+            if app.should_quit {
+                return Ok(());
+            }
 
-                    if app.should_quit {
-                        return Ok(());
-                    }
+            // Handle pending actions (spawning external processes, IAM connections, etc.)
+            if let Some(pending) = app.pending_action.take() {
+                handle_pending_action(terminal, app, pending)?;
+                // Time spent in psql or a session isn't idle time in the TUI
+                last_activity = Instant::now();
+            }
+        }
+    }
+}
 
-                    // Handle pending actions (spawning external processes, IAM connections, etc.)
-                    if let Some(pending) = app.pending_action.take() {
-                        handle_pending_action(terminal, app, pending)?;
-                    }
+fn handle_key(app: &mut App, key: KeyEvent) -> Result<()> {
+    // Handle Ctrl+C globally - show quit confirmation
+    if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+        app.mode = AppMode::ConfirmQuit;
+        return Ok(());
+    }
+
+    match app.mode {
+        AppMode::List => handle_list_input(app, key.code)?,
+        AppMode::Actions => handle_actions_input(app, key.code)?,
+        AppMode::AddForm => handle_form_input(app, key.code),
+        AppMode::ProfileSelector => handle_profile_input(app, key.code)?,
+        AppMode::Connecting => {}
+        AppMode::ConfirmDelete => handle_confirm_delete_input(app, key.code)?,
+        AppMode::ConfirmQuit => handle_confirm_quit_input(app, key.code),
+        AppMode::ConfirmOverwrite => handle_confirm_overwrite_input(app, key.code),
+        AppMode::Recent => handle_recent_input(app, key.code)?,
+        AppMode::Search => handle_search_input(app, key.code),
+        AppMode::ConnectFailed => handle_connect_failed_input(app, key.code)?,
+        AppMode::TagEdit => handle_tag_edit_input(app, key.code)?,
+    }
+    Ok(())
+}
+
+/// Map a mouse event onto the areas recorded during the last draw.
+fn handle_mouse(app: &mut App, mouse: MouseEvent) -> Result<()> {
+    let position = Position::new(mouse.column, mouse.row);
+    let left_click = mouse.kind == MouseEventKind::Down(MouseButton::Left);
+
+    match app.mode {
+        AppMode::List => match mouse.kind {
+            MouseEventKind::ScrollDown => app.next_connection(),
+            MouseEventKind::ScrollUp => app.prev_connection(),
+            _ if left_click => {
+                let clicked = app
+                    .hit_areas
+                    .borrow()
+                    .connection_rows
+                    .and_then(|rows| rows.item_at(mouse.column, mouse.row))
+                    .filter(|&index| index < app.connection_names.len());
+
+                // Clicking the highlighted row again acts like Enter
+                match clicked {
+                    Some(index) if index == app.selected_index => app.activate_selected()?,
+                    Some(index) => app.selected_index = index,
+                    None => {}
+                }
+            }
+            _ => {}
+        },
+        AppMode::Actions if left_click => {
+            let (inside_popup, clicked) = {
+                let hit_areas = app.hit_areas.borrow();
+                (
+                    hit_areas.action_popup.is_some_and(|area| area.contains(position)),
+                    hit_areas
+                        .action_rows
+                        .and_then(|rows| rows.item_at(mouse.column, mouse.row)),
+                )
+            };
+
+            match clicked {
+                Some(index) if index < app.available_actions().len() => {
+                    app.selected_action = index;
+                    app.execute_action()?;
+                }
+                _ if !inside_popup => app.mode = AppMode::List,
+                _ => {}
+            }
+        }
+        AppMode::AddForm if left_click => {
+            let clicked = app
+                .hit_areas
+                .borrow()
+                .form_fields
+                .iter()
+                .position(|area| area.contains(position));
+
+            if let Some(index) = clicked {
+                app.form_state.select(index);
+                if app.form_state.current_field == index {
+                    activate_form_field(app);
                 }
             }
         }
+        _ => {}
     }
+    Ok(())
 }
 
 fn handle_pending_action<B: Backend>(
@@ -105,10 +287,16 @@ fn handle_pending_action<B: Backend>(
             // Simple spawn - suspend TUI and run
             suspend_and_run(terminal, action, app)?;
         }
-        PendingAction::IamConnect { connection_info, profile } => {
+        PendingAction::SsoLogin { profile } => {
+            run_sso_login(terminal, app, profile)?;
+        }
+        PendingAction::IamConnect {
+            name,
+            connection_info,
+            profile,
+        } => {
             // Show loading message before suspending
-            restore_terminal()?;
-            print!("\x1B[2J\x1B[H");
+            suspend_terminal(terminal)?;
             println!("Generating IAM authentication token...");
             println!("Profile: {}", profile.as_deref().unwrap_or("default"));
             println!();
@@ -116,10 +304,12 @@ fn handle_pending_action<B: Backend>(
 
             // Generate IAM token (this is the slow part)
             match generate_iam_token(
+                &SystemRunner,
                 &connection_info.host,
                 connection_info.port,
                 &connection_info.username,
                 profile.as_deref(),
+                iam_region(&connection_info).as_deref(),
             ) {
                 Ok(iam_token) => {
                     println!("Token generated successfully. Connecting to PostgreSQL...");
@@ -130,17 +320,21 @@ fn handle_pending_action<B: Backend>(
                     let sigint_flag = Arc::new(AtomicBool::new(false));
                     let _ = flag::register(SIGINT, Arc::clone(&sigint_flag));
 
-                    // Spawn psql with IAM token
-                    let result = spawn_psql_iam(&connection_info, &iam_token);
+                    // Spawn psql with IAM token, tunneling after the token is signed for the real endpoint
+                    let result = pre_connect(&SystemRunner, &name, &connection_info)
+                        .and_then(|()| tunnel::open_for(&connection_info))
+                        .and_then(|(info, _tunnel)| {
+                            spawn_psql_iam(&SystemRunner, &name, &info, iam_token.expose(), &PsqlOptions::default())
+                        })
+                        .map(|elapsed| after_session(&name, &connection_info, elapsed));
                     sigint_flag.store(false, Ordering::Relaxed);
 
                     // Resume TUI
-                    enable_raw_mode()?;
-                    execute!(io::stdout(), EnterAlternateScreen)?;
-                    terminal.clear()?;
+                    resume_terminal(terminal)?;
 
-                    if let Err(e) = result {
-                        app.status_message = Some(format!("Error: {}", e));
+                    match result {
+                        Ok(()) => app.connect_succeeded(),
+                        Err(e) => app.connect_failed(e.to_string()),
                     }
                 }
                 Err(e) => {
@@ -155,27 +349,26 @@ fn handle_pending_action<B: Backend>(
                         let sigint_flag = Arc::new(AtomicBool::new(false));
                         let _ = flag::register(SIGINT, Arc::clone(&sigint_flag));
 
-                        let sso_result = spawn_sso_login(profile.as_deref());
+                        let sso_result = sso_login(&SystemRunner, profile.as_deref());
                         sigint_flag.store(false, Ordering::Relaxed);
 
                         // Resume TUI
-                        enable_raw_mode()?;
-                        execute!(io::stdout(), EnterAlternateScreen)?;
-                        terminal.clear()?;
-
-                        if sso_result.is_ok() {
-                            // SSO login succeeded - automatically retry
-                            app.status_message = Some("SSO login successful. Retrying connection...".to_string());
-                            app.retry_iam_connect(connection_info, profile);
-                        } else {
-                            app.status_message = Some(format!("SSO login failed: {}", sso_result.unwrap_err()));
+                        resume_terminal(terminal)?;
+
+                        match sso_result {
+                            Ok(()) => {
+                                // SSO login succeeded - automatically retry
+                                app.status_message = Some("SSO login successful. Retrying connection...".to_string());
+                                app.retry_iam_connect(name, *connection_info, profile);
+                            }
+                            Err(e) => {
+                                app.connect_failed(format!("SSO login failed: {}", e));
+                            }
                         }
                     } else {
                         // Other error - just show message
-                        enable_raw_mode()?;
-                        execute!(io::stdout(), EnterAlternateScreen)?;
-                        terminal.clear()?;
-                        app.status_message = Some(format!("Error: Failed to generate IAM token: {}", e));
+                        resume_terminal(terminal)?;
+                        app.connect_failed(format!("Failed to generate IAM token: {}", e));
                     }
                 }
             }
@@ -184,90 +377,62 @@ fn handle_pending_action<B: Backend>(
     Ok(())
 }
 
-fn suspend_and_run<B: Backend>(
+fn run_sso_login<B: Backend>(
     terminal: &mut Terminal<B>,
-    action: Box<dyn FnOnce() -> Result<()>>,
     app: &mut App,
+    profile: Option<String>,
 ) -> Result<()> {
-    // Suspend TUI
-    restore_terminal()?;
-
-    // Clear screen and reset cursor for clean handoff
-    print!("\x1B[2J\x1B[H");
+    suspend_terminal(terminal)?;
+    println!("Running AWS SSO login...");
+    println!();
     io::stdout().flush()?;
 
-    // Ignore SIGINT while child process runs so Ctrl+C goes to child only
+    // Ignore SIGINT while SSO login runs
     let sigint_flag = Arc::new(AtomicBool::new(false));
     let _ = flag::register(SIGINT, Arc::clone(&sigint_flag));
 
-    // Execute the action
-    let result = action();
-
-    // Clear the flag (we don't care if Ctrl+C was pressed)
+    let result = sso_login(&SystemRunner, profile.as_deref());
     sigint_flag.store(false, Ordering::Relaxed);
 
     // Resume TUI
-    enable_raw_mode()?;
-    execute!(io::stdout(), EnterAlternateScreen)?;
-    terminal.clear()?;
-
-    if let Err(e) = result {
-        app.status_message = Some(format!("Error: {}", e));
-    }
-
-    Ok(())
-}
+    resume_terminal(terminal)?;
 
-fn spawn_psql_iam(info: &crate::config::ConnectionInfo, iam_token: &str) -> Result<()> {
-    use std::process::{Command, Stdio};
-    use urlencoding::encode;
-
-    let encoded_token = encode(iam_token);
-    let mut cmd = Command::new("psql");
-    cmd.arg(format!(
-        "postgres://{}:{}@{}:{}/{}?sslmode=require",
-        info.username,
-        encoded_token,
-        info.host,
-        info.port,
-        info.database
-    ))
-    .env("PGPASSWORD", iam_token)
-    .stdin(Stdio::inherit())
-    .stdout(Stdio::inherit())
-    .stderr(Stdio::inherit());
-
-    let status = cmd.status().context(
-        "Failed to execute psql command. Make sure psql is installed and in your PATH.",
-    )?;
-
-    if !status.success() {
-        anyhow::bail!("psql exited with error code: {:?}", status.code());
+    match result {
+        Ok(()) => {
+            app.status_message = Some("SSO login successful. Retrying connection...".to_string());
+            app.retry_last_attempt()?;
+        }
+        Err(e) => app.connect_failed(format!("SSO login failed: {}", e)),
     }
     Ok(())
 }
 
-fn spawn_sso_login(profile: Option<&str>) -> Result<()> {
-    use std::process::{Command, Stdio};
+fn suspend_and_run<B: Backend>(
+    terminal: &mut Terminal<B>,
+    action: Box<dyn FnOnce() -> Result<()>>,
+    app: &mut App,
+) -> Result<()> {
+    // Suspend TUI, leaving a clean screen for the handoff
+    suspend_terminal(terminal)?;
 
-    let mut cmd = Command::new("aws");
-    cmd.arg("sso").arg("login");
+    // Ignore SIGINT while child process runs so Ctrl+C goes to child only
+    let sigint_flag = Arc::new(AtomicBool::new(false));
+    let _ = flag::register(SIGINT, Arc::clone(&sigint_flag));
 
-    if let Some(profile_name) = profile {
-        cmd.args(["--profile", profile_name]);
-    }
+    // Execute the action
+    let result = action();
 
-    cmd.stdin(Stdio::inherit())
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit());
+    // Clear the flag (we don't care if Ctrl+C was pressed)
+    sigint_flag.store(false, Ordering::Relaxed);
 
-    let status = cmd.status().context(
-        "Failed to execute AWS SSO login. Make sure AWS CLI v2 is installed.",
-    )?;
+    // Resume TUI
+    resume_terminal(terminal)?;
 
-    if !status.success() {
-        anyhow::bail!("AWS SSO login exited with error code: {:?}", status.code());
+    match result {
+        Ok(()) => app.connect_succeeded(),
+        Err(e) => app.connect_failed(e.to_string()),
     }
+
     Ok(())
 }
 
@@ -276,19 +441,25 @@ fn handle_list_input(app: &mut App, key: KeyCode) -> Result<()> {
         KeyCode::Char('q') => app.mode = AppMode::ConfirmQuit,
         KeyCode::Char('j') | KeyCode::Down => app.next_connection(),
         KeyCode::Char('k') | KeyCode::Up => app.prev_connection(),
-        KeyCode::Enter => {
-            if !app.connection_names.is_empty() {
-                app.mode = AppMode::Actions;
-                app.selected_action = 0;
-            }
+        KeyCode::Enter => app.activate_selected()?,
+        KeyCode::Char('o') if !app.connection_names.is_empty() => {
+            app.mode = AppMode::Actions;
+            app.selected_action = 0;
         }
         KeyCode::Char('a') => {
             app.mode = AppMode::AddForm;
             app.form_state.reset();
         }
-        KeyCode::Char('d') => {
-            if !app.connection_names.is_empty() {
-                app.mode = AppMode::ConfirmDelete;
+        KeyCode::Char('d') if !app.connection_names.is_empty() => {
+            app.mode = AppMode::ConfirmDelete;
+        }
+        KeyCode::Char('t') => app.start_tag_edit(),
+        KeyCode::Char('s') => app.cycle_sort_field(),
+        KeyCode::Char('S') => app.reverse_sort(),
+        KeyCode::Char(' ') | KeyCode::Char('x') => app.toggle_details(),
+        KeyCode::Char('p') if app.connection_names.is_empty() => {
+            if let Err(e) = app.import_pgpass() {
+                app.status_message = Some(format!("Error: {}", e));
             }
         }
         KeyCode::Char('/') => {
@@ -303,6 +474,25 @@ fn handle_list_input(app: &mut App, key: KeyCode) -> Result<()> {
     Ok(())
 }
 
+fn handle_recent_input(app: &mut App, key: KeyCode) -> Result<()> {
+    match key {
+        KeyCode::Esc => app.mode = AppMode::List,
+        KeyCode::Char('j') | KeyCode::Down => app.next_recent(),
+        KeyCode::Char('k') | KeyCode::Up => app.prev_recent(),
+        KeyCode::Enter => app.connect_recent()?,
+        KeyCode::Char(c @ '1'..='9') => {
+            let index = c as usize - '1' as usize;
+            if index < app.recent.len() {
+                app.selected_recent = index;
+                app.connect_recent()?;
+            }
+        }
+        KeyCode::Char('q') => app.mode = AppMode::ConfirmQuit,
+        _ => {}
+    }
+    Ok(())
+}
+
 fn handle_actions_input(app: &mut App, key: KeyCode) -> Result<()> {
     match key {
         KeyCode::Esc => app.mode = AppMode::List,
@@ -322,26 +512,48 @@ fn handle_form_input(app: &mut App, key: KeyCode) {
         }
         KeyCode::Tab => app.form_state.next_field(),
         KeyCode::BackTab => app.form_state.prev_field(),
-        KeyCode::Enter => {
-            if app.form_state.current_field == 6 {
-                // Submit button
-                if let Err(e) = app.submit_form() {
-                    app.status_message = Some(format!("Error: {}", e));
-                } else {
-                    app.status_message = Some("Connection added successfully".to_string());
-                }
-            }
-        }
-        KeyCode::Char(' ') if app.form_state.current_field == 5 => {
-            // IAM checkbox toggle
-            app.form_state.iam = !app.form_state.iam;
-        }
+        KeyCode::Enter => match app.form_state.current().kind() {
+            FieldKind::Toggle => app.form_state.toggle(app.form_state.current()),
+            // Submit from the password field or the Submit button
+            FieldKind::Secret | FieldKind::Button => submit_form(app),
+            _ => app.form_state.next_field(),
+        },
         KeyCode::Char(c) => app.form_state.handle_char(c),
         KeyCode::Backspace => app.form_state.handle_backspace(),
         _ => {}
     }
 }
 
+/// A click on a toggle flips it and a click on Submit submits; text
+/// fields just take focus.
+fn activate_form_field(app: &mut App) {
+    let field = app.form_state.current();
+    match field.kind() {
+        FieldKind::Toggle => app.form_state.toggle(field),
+        FieldKind::Button => submit_form(app),
+        _ => {}
+    }
+}
+
+fn submit_form(app: &mut App) {
+    if app.submit_would_overwrite() {
+        app.mode = AppMode::ConfirmOverwrite;
+        return;
+    }
+    save_form(app);
+}
+
+fn save_form(app: &mut App) {
+    let editing = app.form_state.editing;
+    if let Err(e) = app.submit_form() {
+        app.status_message = Some(format!("Error: {}", e));
+    } else if editing {
+        app.status_message = Some("Connection updated successfully".to_string());
+    } else {
+        app.status_message = Some("Connection added successfully".to_string());
+    }
+}
+
 fn handle_profile_input(app: &mut App, key: KeyCode) -> Result<()> {
     if app.profile_search_active {
         // Search mode within profile selector
@@ -399,6 +611,18 @@ fn handle_confirm_delete_input(app: &mut App, key: KeyCode) -> Result<()> {
     Ok(())
 }
 
+fn handle_confirm_overwrite_input(app: &mut App, key: KeyCode) {
+    match key {
+        // Back to the form with everything still filled in
+        KeyCode::Esc | KeyCode::Char('n') => app.mode = AppMode::AddForm,
+        KeyCode::Char('y') | KeyCode::Enter => {
+            app.mode = AppMode::AddForm;
+            save_form(app);
+        }
+        _ => {}
+    }
+}
+
 fn handle_confirm_quit_input(app: &mut App, key: KeyCode) {
     match key {
         KeyCode::Esc | KeyCode::Char('n') => app.mode = AppMode::List,
@@ -407,6 +631,34 @@ fn handle_confirm_quit_input(app: &mut App, key: KeyCode) {
     }
 }
 
+fn handle_connect_failed_input(app: &mut App, key: KeyCode) -> Result<()> {
+    match key {
+        KeyCode::Esc | KeyCode::Char('q') => {
+            app.connect_error = None;
+            app.mode = AppMode::List;
+        }
+        KeyCode::Char('r') | KeyCode::Enter => app.retry_last_attempt()?,
+        KeyCode::Char('e') => app.edit_last_attempt(),
+        KeyCode::Char('s') if app.failed_attempt_uses_iam() => app.sso_login_for_last_attempt(),
+        KeyCode::Char('p') if app.failed_attempt_uses_iam() => app.choose_profile_for_last_attempt(),
+        _ => {}
+    }
+    Ok(())
+}
+
+fn handle_tag_edit_input(app: &mut App, key: KeyCode) -> Result<()> {
+    match key {
+        KeyCode::Esc => app.mode = AppMode::List,
+        KeyCode::Enter => app.submit_tag_edit()?,
+        KeyCode::Backspace => {
+            app.tag_input.pop();
+        }
+        KeyCode::Char(c) => app.tag_input.push(c),
+        _ => {}
+    }
+    Ok(())
+}
+
 fn handle_search_input(app: &mut App, key: KeyCode) {
     match key {
         KeyCode::Esc => {
@@ -421,6 +673,7 @@ fn handle_search_input(app: &mut App, key: KeyCode) {
             app.search_query.pop();
             app.update_search_matches();
         }
+        KeyCode::Tab => app.complete_search(),
         KeyCode::Char(c) => {
             app.search_query.push(c);
             app.update_search_matches();
@@ -2,19 +2,25 @@ use ratatui::{
     layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Line, Span},
-    widgets::{Block, Borders, Clear, Paragraph},
+    widgets::{Block, Borders, Clear, Paragraph, Wrap},
     Frame,
 };
 
 use super::app::{App, AppMode};
+use crate::config::DefaultAction;
 use super::widgets::{
     actions::render_actions_popup,
     add_form::render_add_form,
     connection_list::render_connection_list,
     profile_selector::render_profile_selector,
+    recent::render_recent_popup,
+    tag_editor::render_tag_editor,
 };
 
 pub fn draw(f: &mut Frame, app: &App) {
+    // Popups only record their areas while they are on screen
+    *app.hit_areas.borrow_mut() = Default::default();
+
     let show_search_bar = app.mode == AppMode::Search || !app.search_query.is_empty();
 
     let chunks = Layout::default()
@@ -59,6 +65,13 @@ pub fn draw(f: &mut Frame, app: &App) {
         AppMode::ProfileSelector => render_profile_selector(f, app),
         AppMode::ConfirmDelete => render_confirm_delete(f, app),
         AppMode::ConfirmQuit => render_confirm_quit(f),
+        AppMode::ConfirmOverwrite => {
+            render_add_form(f, app);
+            render_confirm_overwrite(f, app);
+        }
+        AppMode::Recent => render_recent_popup(f, app),
+        AppMode::ConnectFailed => render_connect_failed(f, app),
+        AppMode::TagEdit => render_tag_editor(f, app),
         AppMode::List | AppMode::Connecting | AppMode::Search => {}
     }
 
@@ -123,31 +136,50 @@ fn render_search_bar(f: &mut Frame, area: Rect, app: &App) {
 fn render_footer(f: &mut Frame, area: Rect, app: &App) {
     let keybindings = match app.mode {
         AppMode::List => {
-            if !app.search_matches.is_empty() {
+            let enter = match app.settings.default_action {
+                DefaultAction::Menu => "Actions",
+                DefaultAction::Connect | DefaultAction::IamConnect => "Connect",
+                DefaultAction::Session => "Session",
+            };
+            let mut keys = if !app.search_matches.is_empty() {
                 vec![
                     ("q", "Quit"),
                     ("j/k", "Navigate"),
                     ("n/N", "Next/Prev match"),
                     ("Esc", "Clear search"),
-                    ("Enter", "Actions"),
+                    ("Enter", enter),
                 ]
             } else {
                 vec![
                     ("q", "Quit"),
                     ("j/k", "Navigate"),
                     ("/", "Search"),
-                    ("Enter", "Actions"),
+                    ("Enter", enter),
                     ("a", "Add"),
+                    ("t", "Tags"),
                     ("d", "Delete"),
+                    ("s/S", "Sort/Reverse"),
+                    ("x", "Details"),
                 ]
+            };
+            if app.settings.default_action != DefaultAction::Menu {
+                keys.push(("o", "Actions"));
             }
+            keys
         }
         AppMode::Search => {
             vec![
                 ("Esc", "Cancel"),
+                ("Tab", "Complete"),
                 ("Enter", "Confirm"),
             ]
         }
+        AppMode::TagEdit => {
+            vec![
+                ("Esc", "Cancel"),
+                ("Enter", "Save tags"),
+            ]
+        }
         AppMode::Actions => {
             vec![
                 ("Esc", "Back"),
@@ -163,6 +195,14 @@ fn render_footer(f: &mut Frame, area: Rect, app: &App) {
                 ("Enter", "Submit"),
             ]
         }
+        AppMode::Recent => {
+            vec![
+                ("Esc", "All connections"),
+                ("j/k", "Navigate"),
+                ("1-9", "Pick"),
+                ("Enter", "Connect"),
+            ]
+        }
         AppMode::ProfileSelector => {
             vec![
                 ("Esc", "Cancel"),
@@ -170,7 +210,7 @@ fn render_footer(f: &mut Frame, area: Rect, app: &App) {
                 ("Enter", "Select"),
             ]
         }
-        AppMode::ConfirmDelete | AppMode::ConfirmQuit => {
+        AppMode::ConfirmDelete | AppMode::ConfirmQuit | AppMode::ConfirmOverwrite => {
             vec![
                 ("y/Enter", "Confirm"),
                 ("n/Esc", "Cancel"),
@@ -179,6 +219,15 @@ fn render_footer(f: &mut Frame, area: Rect, app: &App) {
         AppMode::Connecting => {
             vec![("", "Connecting...")]
         }
+        AppMode::ConnectFailed => {
+            let mut keys = vec![("r/Enter", "Retry"), ("e", "Edit")];
+            if app.failed_attempt_uses_iam() {
+                keys.push(("s", "SSO login"));
+                keys.push(("p", "Other profile"));
+            }
+            keys.push(("Esc", "Back"));
+            keys
+        }
     };
 
     let spans: Vec<Span> = keybindings
@@ -242,6 +291,39 @@ fn render_confirm_delete(f: &mut Frame, app: &App) {
     f.render_widget(popup, area);
 }
 
+fn render_confirm_overwrite(f: &mut Frame, app: &App) {
+    let area = centered_rect(50, 20, f.area());
+
+    let popup = Paragraph::new(vec![
+        Line::from(""),
+        Line::from(Span::styled(
+            "Replace Connection?",
+            Style::default().add_modifier(Modifier::BOLD),
+        )),
+        Line::from(""),
+        Line::from(format!("A connection named '{}' already exists.", app.form_state.name)),
+        Line::from(""),
+        Line::from("Saving will replace its settings and stored password."),
+        Line::from(""),
+        Line::from(vec![
+            Span::styled(" y ", Style::default().fg(Color::Black).bg(Color::Red)),
+            Span::raw(" Replace  "),
+            Span::styled(" n ", Style::default().fg(Color::Black).bg(Color::Green)),
+            Span::raw(" Back to form"),
+        ]),
+    ])
+    .block(
+        Block::default()
+            .title(" Confirm Replace ")
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(Color::Red)),
+    )
+    .centered();
+
+    f.render_widget(Clear, area);
+    f.render_widget(popup, area);
+}
+
 fn render_confirm_quit(f: &mut Frame) {
     let area = centered_rect(40, 20, f.area());
 
@@ -273,6 +355,55 @@ fn render_confirm_quit(f: &mut Frame) {
     f.render_widget(popup, area);
 }
 
+fn render_connect_failed(f: &mut Frame, app: &App) {
+    let area = centered_rect(60, 30, f.area());
+
+    let name = app
+        .last_attempt
+        .as_ref()
+        .map(|attempt| attempt.name.as_str())
+        .unwrap_or("unknown");
+    let error = app.connect_error.as_deref().unwrap_or("Unknown error");
+
+    let mut options = vec![
+        Span::styled(" r ", Style::default().fg(Color::Black).bg(Color::Green)),
+        Span::raw(" Retry  "),
+        Span::styled(" e ", Style::default().fg(Color::Black).bg(Color::Cyan)),
+        Span::raw(" Edit  "),
+    ];
+    if app.failed_attempt_uses_iam() {
+        options.push(Span::styled(" s ", Style::default().fg(Color::Black).bg(Color::Yellow)));
+        options.push(Span::raw(" SSO login  "));
+        options.push(Span::styled(" p ", Style::default().fg(Color::Black).bg(Color::Magenta)));
+        options.push(Span::raw(" Other profile  "));
+    }
+    options.push(Span::styled(" Esc ", Style::default().fg(Color::Black).bg(Color::DarkGray)));
+    options.push(Span::raw(" Back"));
+
+    let popup = Paragraph::new(vec![
+        Line::from(""),
+        Line::from(Span::styled(
+            format!("Connection to '{}' failed", name),
+            Style::default().add_modifier(Modifier::BOLD),
+        )),
+        Line::from(""),
+        Line::from(Span::styled(error, Style::default().fg(Color::Red))),
+        Line::from(""),
+        Line::from(options),
+    ])
+    .block(
+        Block::default()
+            .title(" Connection Failed ")
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(Color::Red)),
+    )
+    .wrap(Wrap { trim: true })
+    .centered();
+
+    f.render_widget(Clear, area);
+    f.render_widget(popup, area);
+}
+
 fn render_status_message(f: &mut Frame, msg: &str) {
     let area = Rect {
         x: 1,
@@ -5,7 +5,7 @@ use ratatui::{
     Frame,
 };
 
-use crate::tui::app::App;
+use crate::tui::app::{App, RowArea};
 use crate::tui::ui::centered_rect;
 
 pub fn render_actions_popup(f: &mut Frame, app: &App) {
@@ -28,13 +28,14 @@ pub fn render_actions_popup(f: &mut Frame, app: &App) {
         .map(|(name, _)| format!(" {} ", name))
         .unwrap_or_else(|| " Actions ".to_string());
 
+    let block = Block::default()
+        .title(title)
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Cyan));
+    let inner = block.inner(area);
+
     let list = List::new(items)
-        .block(
-            Block::default()
-                .title(title)
-                .borders(Borders::ALL)
-                .border_style(Style::default().fg(Color::Cyan)),
-        )
+        .block(block)
         .highlight_style(
             Style::default()
                 .bg(Color::Cyan)
@@ -48,4 +49,12 @@ pub fn render_actions_popup(f: &mut Frame, app: &App) {
 
     f.render_widget(Clear, area);
     f.render_stateful_widget(list, area, &mut state);
+
+    let mut hit_areas = app.hit_areas.borrow_mut();
+    hit_areas.action_popup = Some(area);
+    hit_areas.action_rows = Some(RowArea {
+        rows: inner,
+        offset: state.offset(),
+        tall: None,
+    });
 }
@@ -6,18 +6,25 @@ use ratatui::{
     Frame,
 };
 
-use crate::tui::app::{App, FormState};
+use crate::tui::app::{App, FieldKind, FormField};
 use crate::tui::ui::centered_rect;
 
 pub fn render_add_form(f: &mut Frame, app: &App) {
-    let area = centered_rect(60, 70, f.area());
+    // Leave room for the optional fields once they're expanded
+    let height = if app.form_state.show_advanced { 90 } else { 70 };
+    let area = centered_rect(60, height, f.area());
 
     // Clear the area behind the popup
     f.render_widget(Clear, area);
 
     // Render the form border
+    let title = if app.form_state.editing {
+        " Edit Connection "
+    } else {
+        " Add Connection "
+    };
     let block = Block::default()
-        .title(" Add Connection ")
+        .title(title)
         .borders(Borders::ALL)
         .border_style(Style::default().fg(Color::Cyan));
     f.render_widget(block, area);
@@ -30,24 +37,37 @@ pub fn render_add_form(f: &mut Frame, app: &App) {
         height: area.height.saturating_sub(2),
     };
 
-    render_form_fields(f, inner, &app.form_state);
+    render_form_fields(f, inner, app);
+}
+
+/// Rows a field takes up: the Advanced toggle is a single line, everything
+/// else a bordered box.
+fn field_height(field: FormField) -> u16 {
+    if field == FormField::Advanced { 1 } else { 3 }
 }
 
-fn render_form_fields(f: &mut Frame, area: Rect, form: &FormState) {
-    let labels = FormState::field_labels();
+fn render_form_fields(f: &mut Frame, area: Rect, app: &App) {
+    let form = &app.form_state;
+    let fields = form.fields();
 
     // Calculate field layout
     let chunks = Layout::default()
         .direction(Direction::Vertical)
-        .constraints(vec![Constraint::Length(3); 8])
+        .constraints(
+            fields
+                .iter()
+                .map(|&field| Constraint::Length(field_height(field)))
+                .collect::<Vec<_>>(),
+        )
         .split(area);
+    app.hit_areas.borrow_mut().form_fields = chunks.to_vec();
 
-    // Render each field
-    for (i, &label) in labels.iter().enumerate() {
+    for (i, &field) in fields.iter().enumerate() {
         let is_selected = form.current_field == i;
+        let label = field.label();
 
-        // Skip password field if IAM is enabled
-        if i == 6 && form.iam {
+        // The password isn't used by IAM connections
+        if field == FormField::Password && form.iam {
             let disabled = Paragraph::new(Line::from(vec![
                 Span::styled(
                     format!("{}: ", label),
@@ -74,10 +94,20 @@ fn render_form_fields(f: &mut Frame, area: Rect, form: &FormState) {
             Style::default().fg(Color::DarkGray)
         };
 
-        match i {
-            5 => {
-                // IAM Auth checkbox
-                let checkbox = if form.iam { "[x]" } else { "[ ]" };
+        match field.kind() {
+            FieldKind::Toggle if field == FormField::Advanced => {
+                let arrow = if form.show_advanced { "v" } else { ">" };
+                let content = Paragraph::new(Line::from(vec![
+                    Span::styled(format!("{} {} ", arrow, label), style.add_modifier(Modifier::BOLD)),
+                    Span::styled(
+                        "(tags, sslmode, bastion, psql version)",
+                        Style::default().fg(Color::DarkGray),
+                    ),
+                ]));
+                f.render_widget(content, chunks[i]);
+            }
+            FieldKind::Toggle => {
+                let checkbox = if form.is_checked(field) { "[x]" } else { "[ ]" };
                 let content = Paragraph::new(Line::from(vec![
                     Span::styled(format!("{} ", checkbox), style.add_modifier(Modifier::BOLD)),
                     Span::styled("Use IAM Authentication", style),
@@ -90,8 +120,7 @@ fn render_form_fields(f: &mut Frame, area: Rect, form: &FormState) {
                 );
                 f.render_widget(content, chunks[i]);
             }
-            7 => {
-                // Submit button
+            FieldKind::Button => {
                 let button_style = if is_selected {
                     Style::default()
                         .fg(Color::Black)
@@ -101,35 +130,29 @@ fn render_form_fields(f: &mut Frame, area: Rect, form: &FormState) {
                     Style::default().fg(Color::Cyan)
                 };
                 let content = Paragraph::new(Line::from(Span::styled(
-                    "  [ Submit ]  ",
+                    format!("  [ {} ]  ", label),
                     button_style,
                 )))
                 .centered();
                 f.render_widget(content, chunks[i]);
             }
-            _ => {
-                // Text input field
-                let value = match i {
-                    0 => &form.name,
-                    1 => &form.host,
-                    2 => &form.port,
-                    3 => &form.database,
-                    4 => &form.username,
-                    6 => &form.password,
-                    _ => unreachable!(),
-                };
-
-                let display_value = if i == 6 {
-                    // Password field - show asterisks
+            kind @ (FieldKind::Text | FieldKind::Number | FieldKind::Secret) => {
+                let value = form.value(field).map(String::as_str).unwrap_or_default();
+                let display_value = if kind == FieldKind::Secret {
                     "*".repeat(value.len())
                 } else {
-                    value.clone()
+                    value.to_string()
                 };
 
-                let cursor = if is_selected { "_" } else { "" };
+                let cursor = if is_selected && !form.is_disabled(field) { "_" } else { "" };
+                let label = match field {
+                    FormField::Password if form.editing => "Password (blank keeps current)",
+                    _ if field.is_advanced() => &format!("{} (optional)", label),
+                    _ => label,
+                };
 
                 let content = Paragraph::new(Line::from(vec![
-                    Span::styled(&display_value, style),
+                    Span::styled(display_value, style),
                     Span::styled(cursor, Style::default().add_modifier(Modifier::SLOW_BLINK)),
                 ]))
                 .block(
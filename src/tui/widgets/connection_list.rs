@@ -1,12 +1,26 @@
 use ratatui::{
-    layout::{Constraint, Rect},
+    layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
-    text::{Line, Span},
-    widgets::{Block, Borders, Cell, Row, Table, TableState},
+    text::{Line, Span, Text},
+    widgets::{Block, Borders, Cell, Paragraph, Row, Table, TableState, Wrap},
     Frame,
 };
 
-use crate::tui::app::App;
+use crate::config::ConnectionInfo;
+use crate::tui::app::{App, RowArea};
+
+/// Lines shown under the selected name when details are toggled on.
+fn detail_lines(info: &ConnectionInfo) -> Vec<Line<'static>> {
+    let style = Style::default().fg(Color::DarkGray);
+    [
+        format!("host:     {}:{}", info.host, info.port),
+        format!("database: {}", info.database),
+        format!("user:     {}", info.username),
+    ]
+    .into_iter()
+    .map(|text| Line::styled(format!("  {}", text), style))
+    .collect()
+}
 
 fn highlight_match<'a>(name: &'a str, query: &str) -> Line<'a> {
     if query.is_empty() {
@@ -42,51 +56,187 @@ fn highlight_match<'a>(name: &'a str, query: &str) -> Line<'a> {
     }
 }
 
+/// First-run screen shown instead of an empty table.
+fn render_empty_state(f: &mut Frame, area: Rect, app: &App) {
+    let key = |k: &'static str| {
+        Span::styled(
+            format!(" {} ", k),
+            Style::default().fg(Color::Black).bg(Color::Cyan),
+        )
+    };
+
+    let mut lines = vec![
+        Line::from(""),
+        Line::from(Span::styled(
+            "Welcome to pg-vault",
+            Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD),
+        )),
+        Line::from(""),
+        Line::from("Store a PostgreSQL connection once, with its password in your system keychain,"),
+        Line::from("then connect to it with a single keystroke."),
+        Line::from(""),
+        Line::from(vec![key("a"), Span::raw(" Add a connection")]),
+    ];
+
+    match &app.pgpass_file {
+        Some(path) => lines.push(Line::from(vec![
+            key("p"),
+            Span::raw(format!(" Import connections from {}", path.display())),
+        ])),
+        None => lines.push(Line::from(Span::styled(
+            "No ~/.pgpass file found to import from",
+            Style::default().fg(Color::DarkGray),
+        ))),
+    }
+
+    lines.extend([
+        Line::from(""),
+        Line::from(Span::styled(
+            "From the command line you can also run:",
+            Style::default().fg(Color::DarkGray),
+        )),
+        Line::from(Span::styled(
+            "pg-vault store <name> --from-url postgres://user@host:5432/db",
+            Style::default().fg(Color::DarkGray),
+        )),
+    ]);
+
+    let paragraph = Paragraph::new(lines)
+        .block(
+            Block::default()
+                .title(" Connections ")
+                .borders(Borders::ALL)
+                .border_style(Style::default().fg(Color::White)),
+        )
+        .wrap(Wrap { trim: true })
+        .centered();
+    f.render_widget(paragraph, area);
+}
+
+/// Why the selected connection failed last time, so it's seen before retrying.
+fn render_last_error(f: &mut Frame, area: Rect, name: &str, app: &App) {
+    let Some(last_error) = app.last_errors.get(name) else {
+        return;
+    };
+
+    let paragraph = Paragraph::new(Line::from(vec![
+        Span::styled(
+            format!("{}: ", last_error.age()),
+            Style::default().fg(Color::DarkGray),
+        ),
+        Span::styled(last_error.message.as_str(), Style::default().fg(Color::Red)),
+    ]))
+    .block(
+        Block::default()
+            .title(" Last error ")
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(Color::Red)),
+    )
+    .wrap(Wrap { trim: true });
+    f.render_widget(paragraph, area);
+}
+
 pub fn render_connection_list(f: &mut Frame, area: Rect, app: &App) {
+    if app.connection_names.is_empty() {
+        render_empty_state(f, area, app);
+        return;
+    }
+
+    let selected_error = app
+        .selected_connection()
+        .filter(|(name, _)| app.last_errors.contains_key(*name));
+    let area = match selected_error {
+        Some((name, _)) => {
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Min(0), Constraint::Length(4)])
+                .split(area);
+            render_last_error(f, chunks[1], name, app);
+            chunks[0]
+        }
+        None => area,
+    };
+
     let header = Row::new(vec![
         Cell::from("Name").style(Style::default().add_modifier(Modifier::BOLD)),
         Cell::from("Auth").style(Style::default().add_modifier(Modifier::BOLD)),
+        Cell::from("Password").style(Style::default().add_modifier(Modifier::BOLD)),
     ])
     .style(Style::default().fg(Color::Cyan))
     .bottom_margin(1);
 
-    let rows: Vec<Row> = if app.connection_names.is_empty() {
-        vec![Row::new(vec![
-            Cell::from("No connections stored. Press 'a' to add one.")
-                .style(Style::default().fg(Color::DarkGray)),
-            Cell::from(""),
-        ])]
-    } else {
-        app.connection_names
-            .iter()
-            .map(|name| {
-                let info = app.connections.get(name).unwrap();
-                let auth_cell = if info.iam_auth {
-                    Cell::from("IAM").style(
-                        Style::default()
-                            .fg(Color::Yellow)
-                            .add_modifier(Modifier::BOLD),
-                    )
-                } else {
-                    Cell::from("PWD").style(Style::default().fg(Color::Green))
-                };
-
-                let name_cell = Cell::from(highlight_match(name, &app.search_query));
-                Row::new(vec![name_cell, auth_cell])
-            })
-            .collect()
-    };
+    let expanded = app.show_details.then_some(app.selected_index);
+    let mut tall = None;
+    let rows: Vec<Row> = app
+        .connection_names
+        .iter()
+        .enumerate()
+        .map(|(index, name)| {
+            let info = app.connections.get(name).unwrap();
+            let auth_cell = if info.iam_auth {
+                Cell::from("IAM").style(
+                    Style::default()
+                        .fg(Color::Yellow)
+                        .add_modifier(Modifier::BOLD),
+                )
+            } else if info.cloud_sql_instance.is_some() {
+                Cell::from("GCP").style(Style::default().fg(Color::Blue))
+            } else if info.plaintext_password {
+                Cell::from("TXT").style(Style::default().fg(Color::Red).add_modifier(Modifier::BOLD))
+            } else {
+                Cell::from("PWD").style(Style::default().fg(Color::Green))
+            };
+
+            // Unprobed entries stay blank until they're selected
+            let password_cell = if info.iam_auth {
+                Cell::from("-").style(Style::default().fg(Color::DarkGray))
+            } else {
+                match app.password_present.get(name) {
+                    Some(Some(true)) => {
+                        Cell::from("stored").style(Style::default().fg(Color::Green))
+                    }
+                    Some(Some(false)) => Cell::from("! missing").style(
+                        Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+                    ),
+                    Some(None) => {
+                        Cell::from("?").style(Style::default().fg(Color::Yellow))
+                    }
+                    None => Cell::from(""),
+                }
+            };
+
+            let mut name_text = Text::from(highlight_match(name, &app.search_query));
+            if expanded == Some(index) {
+                name_text.extend(detail_lines(info));
+            }
+            let height = name_text.height() as u16;
+            if height > 1 {
+                tall = Some((index, height));
+            }
+            Row::new(vec![Cell::from(name_text), auth_cell, password_cell]).height(height)
+        })
+        .collect();
 
-    let widths = [Constraint::Percentage(80), Constraint::Percentage(20)];
+    let widths = [
+        Constraint::Percentage(60),
+        Constraint::Percentage(20),
+        Constraint::Percentage(20),
+    ];
+
+    let sort = app.settings.sort;
+    let block = Block::default()
+        .title(format!(
+            " Connections — sorted by {} {} ",
+            sort.field.label(),
+            if sort.descending { "↓" } else { "↑" }
+        ))
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::White));
+    let inner = block.inner(area);
 
     let table = Table::new(rows, widths)
         .header(header)
-        .block(
-            Block::default()
-                .title(" Connections ")
-                .borders(Borders::ALL)
-                .border_style(Style::default().fg(Color::White)),
-        )
+        .block(block)
         .row_highlight_style(
             Style::default()
                 .bg(Color::DarkGray)
@@ -95,9 +245,18 @@ pub fn render_connection_list(f: &mut Frame, area: Rect, app: &App) {
         .highlight_symbol(">> ");
 
     let mut state = TableState::default();
-    if !app.connection_names.is_empty() {
-        state.select(Some(app.selected_index));
-    }
+    state.select(Some(app.selected_index));
 
     f.render_stateful_widget(table, area, &mut state);
+
+    // Data rows start below the header and its bottom margin
+    app.hit_areas.borrow_mut().connection_rows = Some(RowArea {
+        rows: Rect {
+            y: inner.y + 2,
+            height: inner.height.saturating_sub(2),
+            ..inner
+        },
+        offset: state.offset(),
+        tall,
+    });
 }
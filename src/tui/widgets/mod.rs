@@ -2,3 +2,5 @@ pub mod actions;
 pub mod add_form;
 pub mod connection_list;
 pub mod profile_selector;
+pub mod recent;
+pub mod tag_editor;
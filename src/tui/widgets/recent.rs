@@ -0,0 +1,48 @@
+use ratatui::{
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Clear, List, ListItem, ListState},
+    Frame,
+};
+
+use crate::config::age_since;
+use crate::tui::app::App;
+use crate::tui::ui::centered_rect;
+
+pub fn render_recent_popup(f: &mut Frame, app: &App) {
+    let area = centered_rect(50, 40, f.area());
+
+    let items: Vec<ListItem> = app
+        .recent
+        .iter()
+        .enumerate()
+        .map(|(i, (name, at))| {
+            ListItem::new(Line::from(vec![
+                Span::styled(format!("{} ", i + 1), Style::default().fg(Color::DarkGray)),
+                Span::raw(name.as_str()),
+                Span::styled(format!("  {}", age_since(*at)), Style::default().fg(Color::DarkGray)),
+            ]))
+        })
+        .collect();
+
+    let list = List::new(items)
+        .block(
+            Block::default()
+                .title(" Recent connections ")
+                .borders(Borders::ALL)
+                .border_style(Style::default().fg(Color::Cyan)),
+        )
+        .highlight_style(
+            Style::default()
+                .bg(Color::Cyan)
+                .fg(Color::Black)
+                .add_modifier(Modifier::BOLD),
+        )
+        .highlight_symbol(">> ");
+
+    let mut state = ListState::default();
+    state.select(Some(app.selected_recent));
+
+    f.render_widget(Clear, area);
+    f.render_stateful_widget(list, area, &mut state);
+}
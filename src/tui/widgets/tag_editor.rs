@@ -0,0 +1,40 @@
+use ratatui::{
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Clear, Paragraph},
+    Frame,
+};
+
+use crate::tui::app::App;
+use crate::tui::ui::centered_rect;
+
+pub fn render_tag_editor(f: &mut Frame, app: &App) {
+    let area = centered_rect(50, 20, f.area());
+
+    let title = app
+        .selected_connection()
+        .map(|(name, _)| format!(" Tags: {} ", name))
+        .unwrap_or_else(|| " Tags ".to_string());
+
+    let popup = Paragraph::new(vec![
+        Line::from(""),
+        Line::from(vec![
+            Span::styled(&app.tag_input, Style::default().fg(Color::Cyan)),
+            Span::styled("_", Style::default().add_modifier(Modifier::SLOW_BLINK)),
+        ]),
+        Line::from(""),
+        Line::from(Span::styled(
+            "Comma-separated, e.g. prod, billing",
+            Style::default().fg(Color::DarkGray),
+        )),
+    ])
+    .block(
+        Block::default()
+            .title(title)
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(Color::Cyan)),
+    );
+
+    f.render_widget(Clear, area);
+    f.render_widget(popup, area);
+}
@@ -0,0 +1,204 @@
+use anyhow::{Context, Result};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::{load_settings, BastionProfile, ConnectionInfo};
+
+const TUNNEL_READY_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// How many ports after a connection's `local_port` to try when it's taken.
+const LOCAL_PORT_SEARCH: u16 = 20;
+
+/// A helper process (an `ssh -N -L` port forward or `cloud-sql-proxy`)
+/// accepting database connections on a local port. It is killed on drop.
+pub struct Tunnel {
+    child: Child,
+    pub local_port: u16,
+    kind: &'static str,
+}
+
+impl Drop for Tunnel {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Tunnel {
+    pub fn ssh(bastion: &BastionProfile, remote_host: &str, remote_port: u16, local_port: Option<u16>) -> Result<Self> {
+        let local_port = choose_local_port(local_port)?;
+        let cmd = ssh_command(bastion, &local_port.to_string(), remote_host, remote_port);
+        Self::spawn(
+            cmd,
+            local_port,
+            "SSH tunnel",
+            "Failed to start ssh. Make sure OpenSSH is installed and in your PATH.",
+        )
+    }
+
+    pub fn cloud_sql(instance: &str, local_port: Option<u16>) -> Result<Self> {
+        let local_port = choose_local_port(local_port)?;
+        let cmd = cloud_sql_proxy_command(instance, &local_port.to_string());
+        Self::spawn(
+            cmd,
+            local_port,
+            "cloud-sql-proxy",
+            "Failed to start cloud-sql-proxy. Make sure it is installed and in your PATH.",
+        )
+    }
+
+    fn spawn(mut cmd: Command, local_port: u16, kind: &'static str, missing: &'static str) -> Result<Self> {
+        cmd.stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::inherit());
+
+        let child = cmd.spawn().context(missing)?;
+        let mut tunnel = Tunnel {
+            child,
+            local_port,
+            kind,
+        };
+        tunnel.wait_until_ready()?;
+        Ok(tunnel)
+    }
+
+    fn wait_until_ready(&mut self) -> Result<()> {
+        let started = Instant::now();
+        loop {
+            if let Some(status) = self.child.try_wait()? {
+                anyhow::bail!("{} exited before it was ready: {}", self.kind, status);
+            }
+            if TcpStream::connect((Ipv4Addr::LOCALHOST, self.local_port)).is_ok() {
+                return Ok(());
+            }
+            if started.elapsed() > TUNNEL_READY_TIMEOUT {
+                anyhow::bail!(
+                    "Timed out waiting for {} on local port {}",
+                    self.kind,
+                    self.local_port
+                );
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+}
+
+/// The `ssh -N -L` invocation forwarding `local_port` to the remote
+/// database through a bastion.
+pub fn ssh_command(
+    bastion: &BastionProfile,
+    local_port: &str,
+    remote_host: &str,
+    remote_port: u16,
+) -> Command {
+    let destination = match &bastion.user {
+        Some(user) => format!("{}@{}", user, bastion.host),
+        None => bastion.host.clone(),
+    };
+
+    let mut cmd = Command::new("ssh");
+    cmd.args(["-N", "-o", "ExitOnForwardFailure=yes", "-L"])
+        .arg(format!("{}:{}:{}", local_port, remote_host, remote_port));
+    if let Some(port) = bastion.port {
+        cmd.args(["-p", &port.to_string()]);
+    }
+    if let Some(identity_file) = &bastion.identity_file {
+        cmd.args(["-i", identity_file]);
+    }
+    // Ends ssh's options, so a destination can never be read as one
+    cmd.arg("--").arg(destination);
+    cmd
+}
+
+/// The `cloud-sql-proxy` (v2) invocation serving a Cloud SQL instance,
+/// given as `project:region:instance`, on `local_port`.
+pub fn cloud_sql_proxy_command(instance: &str, local_port: &str) -> Command {
+    let mut cmd = Command::new("cloud-sql-proxy");
+    cmd.args(["--address", "127.0.0.1", "--port", local_port, instance]);
+    cmd
+}
+
+fn free_local_port() -> Result<u16> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .context("Could not find a free local port for the tunnel")?;
+    Ok(listener.local_addr()?.port())
+}
+
+fn is_port_free(port: u16) -> bool {
+    TcpListener::bind((Ipv4Addr::LOCALHOST, port)).is_ok()
+}
+
+/// The local port for a tunnel: `preferred` if nothing is listening on it,
+/// otherwise the next free one after it, or any free port when there's no
+/// preference. Checked before the tunnel starts, since a port another
+/// tunnel already holds would otherwise look like this tunnel being ready.
+fn choose_local_port(preferred: Option<u16>) -> Result<u16> {
+    let Some(preferred) = preferred else {
+        return free_local_port();
+    };
+    let port = next_free_port(preferred, is_port_free).context(format!(
+        "Local ports {} to {} are all in use; pick another with 'store --local-port'",
+        preferred,
+        preferred.saturating_add(LOCAL_PORT_SEARCH)
+    ))?;
+    if port != preferred {
+        eprintln!("Local port {} is in use, so the tunnel uses {} instead", preferred, port);
+    }
+    Ok(port)
+}
+
+fn next_free_port(preferred: u16, is_free: impl Fn(u16) -> bool) -> Option<u16> {
+    (preferred..=preferred.saturating_add(LOCAL_PORT_SEARCH)).find(|&port| is_free(port))
+}
+
+/// Open the tunnel a connection needs, if any, and return the connection
+/// rewritten to go through it. Keep the returned tunnel alive for as long as
+/// the connection is in use.
+pub fn open_for(info: &ConnectionInfo) -> Result<(ConnectionInfo, Option<Tunnel>)> {
+    let tunnel = if let Some(instance) = &info.cloud_sql_instance {
+        eprintln!("Starting cloud-sql-proxy for '{}'...", instance);
+        Tunnel::cloud_sql(instance, info.local_port)?
+    } else if let Some(bastion_name) = &info.bastion {
+        let settings = load_settings()?;
+        let bastion = settings.bastions.get(bastion_name).context(format!(
+            "Bastion '{}' not found. Add it with 'pg-vault bastion add {}'.",
+            bastion_name, bastion_name
+        ))?;
+
+        eprintln!("Opening SSH tunnel via bastion '{}'...", bastion_name);
+        Tunnel::ssh(bastion, &info.host, info.port, info.local_port)?
+    } else if let Some(jump) = &info.ssh_jump {
+        let bastion = BastionProfile::from_jump(jump)
+            .context(format!("'{}' is not a valid SSH jump host; use [user@]host[:port]", jump))?;
+
+        eprintln!("Opening SSH tunnel via '{}'...", jump);
+        Tunnel::ssh(&bastion, &info.host, info.port, info.local_port)?
+    } else {
+        return Ok((info.clone(), None));
+    };
+
+    let mut tunneled = info.clone();
+    tunneled.host = Ipv4Addr::LOCALHOST.to_string();
+    tunneled.port = tunnel.local_port;
+    Ok((tunneled, Some(tunnel)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn taken_ports_are_skipped() {
+        assert_eq!(next_free_port(15432, |_| true), Some(15432));
+        assert_eq!(next_free_port(15432, |port| port > 15434), Some(15435));
+        assert_eq!(next_free_port(15432, |_| false), None);
+        assert_eq!(next_free_port(u16::MAX, |_| true), Some(u16::MAX));
+
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let taken = listener.local_addr().unwrap().port();
+        assert!(!is_port_free(taken));
+        assert_ne!(choose_local_port(Some(taken)).unwrap(), taken);
+    }
+}